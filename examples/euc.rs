@@ -3,7 +3,7 @@ use std::path::Path;
 use tobj;
 use vek::*;
 use terminal_size::{Width, Height, terminal_size};
-use blockish::render;
+use blockish::{PackedPixels, Renderer};
 
 struct Teapot<'a> {
     mvp: Mat4<f32>,
@@ -98,11 +98,9 @@ if let Some((Width(w), Height(h))) = size {
     let width = _w as u32;
     let height = _h as u32;
     println!("\x1b[{};0f", 0);
-    render(width, height as u32, &|x, y| {
-        let start = (((height - 1 - y) * width + x)) as usize;
-        let colors = raw_slice[start];
-         ((colors >> 16 & 0xff) as u8, (colors >> 8 & 0xff) as u8, ( colors & 0xff) as u8)
-    });
+    Renderer::new(width, height)
+        .render(&PackedPixels::new(raw_slice, width).bottom_up(true))
+        .unwrap();
 
     /*
         if win.is_open() {
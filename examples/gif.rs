@@ -1,6 +1,6 @@
 use std::fs::File;
 use gif::SetParameter;
-use blockish::render;
+use blockish::{Pixels, Renderer};
 
 fn main() {
 
@@ -13,10 +13,9 @@ fn main() {
         while let Some(frame) = decoder.read_next_frame().unwrap() {
             let raw_slice = &frame.buffer;
             println!("\x1b[{};0f", 0);
-            render(frame.width as u32, frame.height as u32, &|x, y| {
-                let start = ((y * frame.width as u32 + x)*4) as usize;
-                (raw_slice[start], raw_slice[start + 1], raw_slice[start + 2])
-            });
+            Renderer::new(frame.width as u32, frame.height as u32)
+                .render(&Pixels::rgba8(raw_slice, frame.width as u32))
+                .unwrap();
         }
     }
 }
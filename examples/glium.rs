@@ -7,7 +7,7 @@ use cgmath::SquareMatrix;
 use glium::{glutin, Surface};
 use std::time::Instant;
 use glutin::dpi::LogicalSize;
-use blockish::render;
+use blockish::{Pixels, Renderer};
 
 fn main() {
     let win_size = LogicalSize {
@@ -324,10 +324,9 @@ fn main() {
         //let image = image::ImageBuffer::from_raw(image.width, image.height, image.data.into_owned()).unwrap();
         let raw_slice = &image.data[..];
         println!("\x1b[{};0f", 0);
-        render(image.width, image.height, &|x, y| {
-            let start = (((image.height - 1 - y) * image.width + x)*4) as usize;
-            (raw_slice[start], raw_slice[start + 1], raw_slice[start + 2])
-        });
+        Renderer::new(image.width, image.height)
+            .render(&Pixels::rgba8(raw_slice, image.width).bottom_up(true))
+            .unwrap();
     });
 }
 
@@ -1,5 +1,5 @@
 extern crate tiny_skia;
-use blockish::render;
+use blockish::{Pixels, Renderer};
 use tiny_skia::*;
 
 fn main() {
@@ -30,20 +30,10 @@ fn main() {
 
         let pixels = pixmap.take();
 
-        render(
-            width,
-            height,
-            &|x, y| {
-                let start = ((y * width as u32 + x) * 4) as usize;
-                (
-                    pixels[start],
-                    pixels[start + 1],
-                    pixels[start + 2],
-                    pixels[start + 3],
-                )
-            },
-            Some((0, 0)),
-        );
+        Renderer::new(width, height)
+            .pos(Some((0, 0)))
+            .render(&Pixels::rgba8(&pixels, width))
+            .unwrap();
     }
 }
 
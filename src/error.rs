@@ -0,0 +1,48 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    /* writing the output failed, e.g. because the reader of a pipe went away */
+    Io(io::Error),
+    /* the image could not be opened or decoded */
+    Image(image::ImageError),
+    /* a thread of the pool stopped without sending its part of the image */
+    Thread,
+    /* the image was to fit the terminal, whose size could not be found */
+    TerminalSize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Image(error) => write!(f, "{}", error),
+            Error::Thread => write!(f, "a rendering thread stopped before finishing"),
+            Error::TerminalSize => write!(f, "the size of the terminal is unknown"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Image(error) => Some(error),
+            Error::Thread | Error::TerminalSize => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Error {
+        Error::Image(error)
+    }
+}
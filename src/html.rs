@@ -0,0 +1,111 @@
+use crate::{palette_color, Cell, ColorMode, Error};
+use std::io::Write;
+
+/* block elements only tile without gaps when lines are exactly one em high */
+const PAGE_START: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<style>
+pre {
+  font-family: \"DejaVu Sans Mono\", Menlo, Consolas, \"Liberation Mono\", \"Noto Sans Mono\", monospace;
+  line-height: 1;
+  letter-spacing: 0;
+}
+</style>
+</head>
+<body>
+";
+const PAGE_END: &str = "</body>
+</html>
+";
+
+fn css_color(color: (u8, u8, u8, u8), mode: ColorMode) -> Option<String> {
+    if color.3 == 0 || mode == ColorMode::None {
+        return None;
+    }
+    let color = palette_color(color, mode);
+    Some(format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2))
+}
+
+/* the style of a cell, where the foreground of a space is left out
+ * so that it merges with its neighbours */
+fn style(cell: &Cell, mode: ColorMode) -> String {
+    let mut fg = if cell.character == ' ' && !cell.attributes.underline {
+        None
+    } else {
+        css_color(cell.fg, mode)
+    };
+    let mut bg = css_color(cell.bg, mode);
+    if cell.attributes.reverse {
+        std::mem::swap(&mut fg, &mut bg);
+    }
+    let mut style = String::new();
+    if let Some(fg) = fg {
+        style.push_str(format!("color:{};", fg).as_str());
+    }
+    if let Some(bg) = bg {
+        style.push_str(format!("background:{};", bg).as_str());
+    }
+    if cell.attributes.bold {
+        style.push_str("font-weight:bold;");
+    }
+    if cell.attributes.italic {
+        style.push_str("font-style:italic;");
+    }
+    if cell.attributes.underline {
+        style.push_str("text-decoration:underline;");
+    }
+    style
+}
+
+fn push_escaped(out: &mut String, character: char) {
+    match character {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        _ => out.push(character),
+    }
+}
+
+/* writes the cells as a <pre> element, with a span for each run of cells sharing
+ * the same style, or as a whole page when standalone */
+pub fn write_html(
+    cells: &[Cell],
+    columns: u32,
+    color_mode: ColorMode,
+    standalone: bool,
+    handle: &mut dyn Write,
+) -> Result<(), Error> {
+    let mut out = String::new();
+    if standalone {
+        out.push_str(PAGE_START);
+    }
+    out.push_str("<pre>");
+    for row in cells.chunks(columns.max(1) as usize) {
+        let mut current = String::new();
+        for cell in row {
+            let style = style(cell, color_mode);
+            if style != current {
+                if !current.is_empty() {
+                    out.push_str("</span>");
+                }
+                if !style.is_empty() {
+                    out.push_str(format!("<span style=\"{}\">", style).as_str());
+                }
+                current = style;
+            }
+            push_escaped(&mut out, cell.character);
+        }
+        if !current.is_empty() {
+            out.push_str("</span>");
+        }
+        out.push('\n');
+    }
+    out.push_str("</pre>\n");
+    if standalone {
+        out.push_str(PAGE_END);
+    }
+    write!(handle, "{}", out)?;
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use crate::pixels::{check_rgba, encode_png};
+use crate::{base64, Error};
+use std::io::Write;
+
+/* displays the image at the cursor as an iTerm2 inline file, stretched over
+ * columns x rows cells like the characters would be */
+pub fn write_iterm(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    cells: (u32, u32),
+    handle: &mut dyn Write,
+) -> Result<(), Error> {
+    check_rgba(rgba, width, height)?;
+    /* the png encoder refuses empty images */
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let png = encode_png(rgba, width, height)?;
+    write!(
+        handle,
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=0:{}\x07",
+        png.len(),
+        cells.0,
+        cells.1,
+        base64(&png)
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_images_draw_nothing() {
+        let mut out = Vec::new();
+        write_iterm(&[], 0, 16, (0, 1), &mut out).unwrap();
+        write_iterm(&[], 8, 0, (1, 0), &mut out).unwrap();
+        assert!(out.is_empty());
+        assert!(write_iterm(&[0; 4], 8, 16, (1, 1), &mut out).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+use crate::pixels::{check_rgba, encode_png};
+use crate::{base64, Error};
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/* the protocol limits the base64 data of an escape code to 4096 bytes */
+const CHUNK_SIZE: usize = 4096;
+
+static NEXT_IMAGE_ID: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Payload {
+    /* bigger but nothing to encode, for local terminals */
+    #[default]
+    Rgba,
+    Png,
+}
+
+/* image ids are shared by all the programs drawing in the terminal,
+ * the process id keeps the ones of different programs apart */
+pub fn new_image_id() -> u32 {
+    let id = std::process::id().wrapping_shl(8) ^ NEXT_IMAGE_ID.fetch_add(1, Ordering::Relaxed);
+    id.max(1)
+}
+
+/* transmits and displays the image at the cursor, scaled to cover columns x rows cells;
+ * with an id, the image and its placement replace the previous ones with this id */
+pub fn write_kitty(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    cells: (u32, u32),
+    image_id: Option<u32>,
+    payload: Payload,
+    handle: &mut dyn Write,
+) -> Result<(), Error> {
+    check_rgba(rgba, width, height)?;
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    /* q=2 keeps the terminal from answering in the input of the program */
+    let mut keys = match payload {
+        Payload::Rgba => format!("a=T,f=32,s={},v={}", width, height),
+        Payload::Png => "a=T,f=100".to_string(),
+    };
+    keys.push_str(format!(",c={},r={},q=2", cells.0, cells.1).as_str());
+    if let Some(id) = image_id {
+        keys.push_str(format!(",i={},p=1", id).as_str());
+    }
+    let data = match payload {
+        Payload::Rgba => base64(rgba),
+        Payload::Png => base64(&encode_png(rgba, width, height)?),
+    };
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(CHUNK_SIZE).collect();
+    let mut out = String::with_capacity(data.len() + chunks.len() * 16 + keys.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        out.push_str("\x1b_G");
+        if i == 0 {
+            out.push_str(format!("{},", keys).as_str());
+        }
+        out.push_str(format!("m={};", more).as_str());
+        /* base64 is ascii, so chunks are always valid utf-8 */
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+    write!(handle, "{}", out)?;
+    Ok(())
+}
+
+/* removes the placements of the image and frees its data */
+pub fn delete_image(image_id: u32, handle: &mut dyn Write) -> Result<(), Error> {
+    write!(handle, "\x1b_Ga=d,d=I,i={},q=2\x1b\\", image_id)?;
+    Ok(())
+}
+
+/* removes every image placed on the screen */
+pub fn delete_all_images(handle: &mut dyn Write) -> Result<(), Error> {
+    write!(handle, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* the payload of each escape code and the keys before it */
+    fn chunks(out: &str) -> Vec<(&str, &str)> {
+        out.split_terminator("\x1b\\")
+            .map(|code| {
+                let code = code.strip_prefix("\x1b_G").unwrap();
+                code.split_once(';').unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn payloads_are_split_in_chunks() {
+        /* 40 x 40 pixels are 6400 bytes, 8536 in base64 */
+        let rgba: Vec<u8> = (0..6400).map(|i| (i * 7) as u8).collect();
+        let mut out = Vec::new();
+        write_kitty(&rgba, 40, 40, (5, 3), Some(7), Payload::Rgba, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let chunks = chunks(&out);
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.0).collect::<Vec<&str>>(),
+            ["a=T,f=32,s=40,v=40,c=5,r=3,q=2,i=7,p=1,m=1", "m=1", "m=0"]
+        );
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| chunk.1.len())
+                .collect::<Vec<usize>>(),
+            [CHUNK_SIZE, CHUNK_SIZE, 8536 - 2 * CHUNK_SIZE]
+        );
+        let data: String = chunks.iter().map(|chunk| chunk.1).collect();
+        assert_eq!(data, base64(&rgba));
+    }
+
+    #[test]
+    fn small_payloads_fit_in_one_code() {
+        let mut out = Vec::new();
+        write_kitty(&[1, 2, 3, 255], 1, 1, (1, 1), None, Payload::Rgba, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b_Ga=T,f=32,s=1,v=1,c=1,r=1,q=2,m=0;AQID/w==\x1b\\"
+        );
+    }
+}
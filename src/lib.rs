@@ -3,8 +3,8 @@ extern crate lazy_static;
 extern crate num_cpus;
 extern crate scoped_threadpool;
 
-use image::imageops::FilterType;
-use image::DynamicImage;
+use image::imageops::{self, FilterType};
+use image::{GenericImageView, Rgba};
 use lazy_static::lazy_static;
 use std::collections::HashMap;
 use std::io::{self, Cursor, Write};
@@ -13,6 +13,40 @@ use scoped_threadpool::Pool;
 use std::str;
 use std::sync::mpsc::channel;
 
+pub mod error;
+pub mod html;
+pub mod iterm;
+pub mod kitty;
+pub mod pixels;
+pub mod sixel;
+pub mod terminal;
+pub use error::Error;
+pub use pixels::{ImageView, PackedPixels, PixelFormat, PixelSource, Pixels};
+pub use terminal::Capabilities;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/* the graphics protocols send their images in base64 */
+fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let triple = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(triple >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
 #[inline(always)]
 fn bit_count(x: u32) -> usize {
     /* first let res = x&0xAAAAAAAA >> 1 + x&55555555
@@ -51,6 +85,8 @@ fn find_closest_group(groups: &[u64], group: u64) -> Option<usize> {
     for template in groups {
         let xor = template ^ group;
         let distance = bit_count((xor >> 32) as u32) + bit_count((xor & 0xffffffff) as u32);
+        /* only strictly closer templates replace the current one,
+         * so the first of equally close templates wins */
         if distance < min_distance {
             min_distance = distance;
             min = Some(i);
@@ -63,8 +99,741 @@ fn find_closest_group(groups: &[u64], group: u64) -> Option<usize> {
     min
 }
 
+/* braille dots are numbered column by column for the first six,
+ * then the bottom row: this maps (column, row) of the 2x4 grid to its bit */
+const BRAILLE_DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+/* a dot is raised when most of the 4x2 pixels it covers are in the mask */
+#[inline(always)]
+fn braille(group: u64) -> char {
+    let mut dots = 0;
+    for (column, column_dots) in BRAILLE_DOTS.iter().enumerate() {
+        for (row, dot) in column_dots.iter().enumerate() {
+            let mut count = 0;
+            for dy in (row * 2)..(row * 2 + 2) {
+                for dx in (column * 4)..(column * 4 + 4) {
+                    if mask_contains(group, dy * 8 + dx) {
+                        count += 1;
+                    }
+                }
+            }
+            if count >= 4 {
+                dots |= dot;
+            }
+        }
+    }
+    std::char::from_u32(0x2800 + dots).unwrap_or(' ')
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    #[default]
+    Blocks,
+    Braille,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Matcher {
+    /* closest glyph to the mask of the pixels brighter than the cell average */
+    #[default]
+    Hamming,
+    /* glyph whose best foreground and background colors are closest to the cell pixels */
+    RgbError,
+    OklabError,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sampling {
+    /* one pixel out of two rows, colors from the diagonal */
+    #[default]
+    Fast,
+    /* every pixel of the cell */
+    Full,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSelection {
+    /* mask from the cell average, colors from the quartiles of the samples */
+    #[default]
+    Quartiles,
+    /* mask and colors from the same two clusters of the cell pixels */
+    KMeans,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Luminance {
+    /* sum of the sRGB channels, colors averaged in sRGB */
+    #[default]
+    Sum,
+    /* Rec.709 luma of the linear channels, colors averaged in linear light */
+    Rec709,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Threshold {
+    #[default]
+    Mean,
+    Median,
+    /* split maximizing the variance between the dark and bright pixels */
+    Otsu,
+    /* halfway between the darkest and the brightest pixels */
+    Midpoint,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    #[default]
+    TrueColor,
+    Xterm256,
+    Ansi16,
+    None,
+}
+
+/* spreads the error made when quantizing the colors of a cell to a palette,
+ * so it only applies to the 256 and 16 color modes */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    FloydSteinberg,
+    Atkinson,
+    Bayer,
+}
+
+/* how the image is drawn: with characters, or with the pixels of a graphics protocol
+ * in the area the characters would have covered */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Cells,
+    /* resampled to the size of the cells in pixels, which the terminal is asked for */
+    Sixel,
+    /* the kitty graphics protocol, also understood by WezTerm and ghostty */
+    Kitty,
+    /* the inline images of iTerm2, also understood by WezTerm and Konsole */
+    Iterm,
+    /* the cells as html, a <pre> element or a whole page */
+    Html {
+        standalone: bool,
+    },
+}
+
+impl Backend {
+    pub fn from_capabilities(capabilities: &Capabilities) -> Backend {
+        if capabilities.kitty_graphics {
+            Backend::Kitty
+        } else if capabilities.iterm_images {
+            Backend::Iterm
+        } else if capabilities.sixel {
+            Backend::Sixel
+        } else {
+            Backend::Cells
+        }
+    }
+
+    /* the best protocol the terminal answers to, or cells when it supports none */
+    pub fn detect() -> Backend {
+        Backend::from_capabilities(&Capabilities::detect_with_queries())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderSettings {
+    pub backend: Backend,
+    pub mode: Mode,
+    pub glyph_set: GlyphSet,
+    pub matcher: Matcher,
+    pub sampling: Sampling,
+    pub color_selection: ColorSelection,
+    pub luminance: Luminance,
+    pub threshold: Threshold,
+    pub color_mode: ColorMode,
+    pub dither: Dither,
+    /* only the characters, without any escape sequence, for terminals which do not
+     * understand them: no colors and no cursor movement */
+    pub plain: bool,
+}
+
+impl RenderSettings {
+    pub fn from_capabilities(capabilities: &Capabilities) -> RenderSettings {
+        let mut glyph_set = if capabilities.unicode {
+            GlyphSet::default()
+        } else {
+            GlyphSet::ascii()
+        };
+        if capabilities.legacy_glyphs {
+            glyph_set = glyph_set.with(&GlyphSet::sextants());
+        }
+        RenderSettings {
+            glyph_set,
+            color_mode: capabilities.color_mode,
+            plain: !capabilities.ansi,
+            ..RenderSettings::default()
+        }
+    }
+
+    /* settings for the terminal the program is running in */
+    pub fn detect() -> RenderSettings {
+        RenderSettings::from_capabilities(&Capabilities::detect())
+    }
+}
+
+/* xterm default colors, the actual ones depend on the terminal theme */
+const ANSI_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+lazy_static! {
+    static ref ANSI_16_OKLAB: Vec<(u8, [f32; 3])> = ANSI_16
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (i as u8, oklab((c.0, c.1, c.2, 255))))
+        .collect();
+    /* the 16 first xterm colors are left out as they depend on the terminal theme */
+    static ref XTERM_256_OKLAB: Vec<(u8, [f32; 3])> = (16..=255)
+        .map(|i| (i as u8, oklab(xterm_256_color(i as u8))))
+        .collect();
+}
+
+fn xterm_256_color(i: u8) -> (u8, u8, u8, u8) {
+    if i < 16 {
+        let c = ANSI_16[i as usize];
+        (c.0, c.1, c.2, 255)
+    } else if i < 232 {
+        let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+        let i = i - 16;
+        (level(i / 36), level((i / 6) % 6), level(i % 6), 255)
+    } else {
+        let grey = 8 + (i - 232) * 10;
+        (grey, grey, grey, 255)
+    }
+}
+
+fn nearest_palette_index(palette: &[(u8, [f32; 3])], color: (u8, u8, u8, u8)) -> u8 {
+    let lab = oklab(color);
+    let mut best = 0;
+    let mut best_distance = f32::MAX;
+    for (i, entry) in palette {
+        let distance =
+            (lab[0] - entry[0]).powi(2) + (lab[1] - entry[1]).powi(2) + (lab[2] - entry[2]).powi(2);
+        if distance < best_distance {
+            best_distance = distance;
+            best = *i;
+        }
+    }
+    best
+}
+
+const FLOYD_STEINBERG: [(isize, usize, f32); 4] = [
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+
+const ATKINSON: [(isize, usize, f32); 6] = [
+    (1, 0, 1.0 / 8.0),
+    (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0),
+    (0, 1, 1.0 / 8.0),
+    (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+fn palette_color(color: (u8, u8, u8, u8), mode: ColorMode) -> (u8, u8, u8, u8) {
+    match mode {
+        ColorMode::Xterm256 => xterm_256_color(nearest_palette_index(&XTERM_256_OKLAB, color)),
+        ColorMode::Ansi16 => xterm_256_color(nearest_palette_index(&ANSI_16_OKLAB, color)),
+        _ => color,
+    }
+}
+
+/* errors holds, for the current cell row and the two next ones,
+ * the error to add to each cell foreground (0..3) and background (3..6),
+ * with two cells of padding on each side */
+fn dither_color(
+    color: (u8, u8, u8, u8),
+    settings: &RenderSettings,
+    errors: &mut [Vec<[f32; 6]>],
+    x: usize,
+    y: usize,
+    channel: usize,
+) -> (u8, u8, u8, u8) {
+    let offset = match settings.dither {
+        Dither::Bayer => {
+            let spread = if settings.color_mode == ColorMode::Ansi16 {
+                128.0
+            } else {
+                40.0
+            };
+            /* the background uses the transposed matrix so it does not follow the foreground */
+            let (row, column) = if channel == 0 { (y, x) } else { (x, y) };
+            let threshold = BAYER[row % 4][column % 4];
+            let offset = (threshold as f32 + 0.5) / 16.0 - 0.5;
+            [offset * spread; 3]
+        }
+        _ => {
+            let error = &errors[0][x + 2];
+            [error[channel], error[channel + 1], error[channel + 2]]
+        }
+    };
+    let adjust = |c: u8, offset: f32| (c as f32 + offset).round().clamp(0.0, 255.0) as u8;
+    let adjusted = (
+        adjust(color.0, offset[0]),
+        adjust(color.1, offset[1]),
+        adjust(color.2, offset[2]),
+        color.3,
+    );
+    let kernel: &[(isize, usize, f32)] = match settings.dither {
+        Dither::FloydSteinberg => &FLOYD_STEINBERG,
+        Dither::Atkinson => &ATKINSON,
+        _ => &[],
+    };
+    if !kernel.is_empty() {
+        let quantized = palette_color(adjusted, settings.color_mode);
+        let error = [
+            color.0 as f32 + offset[0] - quantized.0 as f32,
+            color.1 as f32 + offset[1] - quantized.1 as f32,
+            color.2 as f32 + offset[2] - quantized.2 as f32,
+        ];
+        for (dx, dy, weight) in kernel {
+            let target = &mut errors[*dy][(x as isize + 2 + dx) as usize];
+            for k in 0..3 {
+                target[channel + k] += error[k] * weight;
+            }
+        }
+    }
+    adjusted
+}
+
+/* a color as the terminal sees it, once quantized to the color mode */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Paint {
+    Default,
+    Rgb(u8, u8, u8),
+    Indexed(u8),
+}
+
+fn paint(color: (u8, u8, u8, u8), mode: ColorMode) -> Paint {
+    if color.3 == 0 {
+        return Paint::Default;
+    }
+    match mode {
+        ColorMode::TrueColor => Paint::Rgb(color.0, color.1, color.2),
+        ColorMode::Xterm256 => Paint::Indexed(nearest_palette_index(&XTERM_256_OKLAB, color)),
+        ColorMode::Ansi16 => Paint::Indexed(nearest_palette_index(&ANSI_16_OKLAB, color)),
+        ColorMode::None => Paint::Default,
+    }
+}
+
+fn push_paint(params: &mut String, paint: Paint, background: bool, mode: ColorMode) {
+    if !params.is_empty() {
+        params.push(';');
+    }
+    let base = if background { 40 } else { 30 };
+    match paint {
+        Paint::Default => params.push_str(format!("{}", base + 9).as_str()),
+        Paint::Rgb(r, g, b) => {
+            params.push_str(format!("{};2;{};{};{}", base + 8, r, g, b).as_str())
+        }
+        Paint::Indexed(i) if mode == ColorMode::Ansi16 => params.push_str(
+            format!(
+                "{}",
+                if i < 8 {
+                    base + i as u32
+                } else {
+                    base + 60 + i as u32 - 8
+                }
+            )
+            .as_str(),
+        ),
+        Paint::Indexed(i) => params.push_str(format!("{};5;{}", base + 8, i).as_str()),
+    }
+}
+
+/* keeps track of the colors the terminal currently uses so that only changes are written,
+ * None meaning the colors are not known yet, like at the start of a render */
+struct Sgr {
+    mode: ColorMode,
+    fg: Option<Paint>,
+    bg: Option<Paint>,
+    attributes: Attributes,
+    blanks: usize,
+}
+
+impl Sgr {
+    fn new(settings: &RenderSettings) -> Sgr {
+        Sgr {
+            mode: if settings.plain {
+                ColorMode::None
+            } else {
+                settings.color_mode
+            },
+            fg: None,
+            bg: None,
+            attributes: Attributes::default(),
+            blanks: 0,
+        }
+    }
+
+    fn set(&mut self, out: &mut String, fg: Option<Paint>, bg: Option<Paint>) {
+        let fg = fg.filter(|fg| self.fg != Some(*fg));
+        let bg = bg.filter(|bg| self.bg != Some(*bg));
+        if fg == Some(Paint::Default) && bg == Some(Paint::Default) {
+            out.push_str("\x1b[0m");
+            self.attributes = Attributes::default();
+        } else if fg.is_some() || bg.is_some() {
+            let mut params = String::new();
+            if let Some(fg) = fg {
+                push_paint(&mut params, fg, false, self.mode);
+            }
+            if let Some(bg) = bg {
+                push_paint(&mut params, bg, true, self.mode);
+            }
+            out.push_str(format!("\x1b[{}m", params).as_str());
+        }
+        self.fg = fg.or(self.fg);
+        self.bg = bg.or(self.bg);
+    }
+
+    fn set_attributes(&mut self, out: &mut String, attributes: Attributes) {
+        let current = self.attributes;
+        let mut params = Vec::new();
+        for (on, was, set, unset) in [
+            (attributes.bold, current.bold, "1", "22"),
+            (attributes.italic, current.italic, "3", "23"),
+            (attributes.underline, current.underline, "4", "24"),
+            (attributes.reverse, current.reverse, "7", "27"),
+        ] {
+            if on != was {
+                params.push(if on { set } else { unset });
+            }
+        }
+        if !params.is_empty() {
+            out.push_str(format!("\x1b[{}m", params.join(";")).as_str());
+        }
+        self.attributes = attributes;
+    }
+
+    /* the foreground of a space is not visible, so it is left as is,
+     * and runs of spaces without background are written at once */
+    fn cell(&mut self, out: &mut String, cell: &Cell) {
+        let fg = paint(cell.fg, self.mode);
+        let bg = paint(cell.bg, self.mode);
+        if self.mode == ColorMode::None {
+            out.push(cell.character);
+        } else if cell.character == ' '
+            && bg == Paint::Default
+            && cell.attributes == Attributes::default()
+        {
+            self.blanks += 1;
+        } else {
+            self.flush(out);
+            self.set(
+                out,
+                if cell.character == ' ' {
+                    None
+                } else {
+                    Some(fg)
+                },
+                Some(bg),
+            );
+            self.set_attributes(out, cell.attributes);
+            out.push(cell.character);
+        }
+    }
+
+    /* long runs are erased (ECH) and skipped (CUF), which does not depend on
+     * the terminal erasing with the current background as there is none */
+    fn flush(&mut self, out: &mut String) {
+        if self.blanks == 0 {
+            return;
+        }
+        self.set(out, None, Some(Paint::Default));
+        self.set_attributes(out, Attributes::default());
+        if self.blanks >= 8 {
+            out.push_str(format!("\x1b[{}X\x1b[{}C", self.blanks, self.blanks).as_str());
+        } else {
+            out.push_str(&" ".repeat(self.blanks));
+        }
+        self.blanks = 0;
+    }
+
+    fn reset(&mut self, out: &mut String) {
+        self.flush(out);
+        if self.mode != ColorMode::None {
+            self.set_attributes(out, Attributes::default());
+            self.set(out, Some(Paint::Default), Some(Paint::Default));
+        }
+    }
+}
+
+lazy_static! {
+    static ref SRGB_TO_LINEAR: [f32; 256] = {
+        let mut linear = [0.0; 256];
+        for (i, l) in linear.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *l = if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            };
+        }
+        linear
+    };
+}
+
+fn oklab(rgba: (u8, u8, u8, u8)) -> [f32; 3] {
+    let r = SRGB_TO_LINEAR[rgba.0 as usize];
+    let g = SRGB_TO_LINEAR[rgba.1 as usize];
+    let b = SRGB_TO_LINEAR[rgba.2 as usize];
+    let l = (0.41222146 * r + 0.53633255 * g + 0.051445995 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171885 * g + 0.6299787 * b).cbrt();
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+/* pixel i of a cell is bit 63 - i of a mask */
+#[inline(always)]
+fn mask_contains(mask: u64, i: usize) -> bool {
+    (mask >> (63 - i)) & 1 == 1
+}
+
+/* the error of a glyph is the sum of the squared distances of the pixels
+ * to the average of their side of the mask, which is
+ * sum(|c|²) - |sum(on)|² / count(on) - |sum(off)|² / count(off):
+ * the glyph with the lowest error maximizes the two last terms;
+ * sums are in f64 and a glyph only replaces the best one when its score is
+ * higher by more than the rounding errors, so the first of equal glyphs wins */
+fn find_lowest_color_error(groups: &[u64], colors: &[[f32; 3]; 64]) -> Option<usize> {
+    let mut total = [0.0f64; 3];
+    for color in colors.iter() {
+        for k in 0..3 {
+            total[k] += color[k] as f64;
+        }
+    }
+    let mut best: Option<usize> = None;
+    let mut best_score = 0.0;
+    for (i, group) in groups.iter().enumerate() {
+        let mut on = [0.0f64; 3];
+        let mut count = 0;
+        for (j, color) in colors.iter().enumerate() {
+            if mask_contains(*group, j) {
+                for k in 0..3 {
+                    on[k] += color[k] as f64;
+                }
+                count += 1;
+            }
+        }
+        let mut score = 0.0;
+        for k in 0..3 {
+            if count > 0 {
+                score += on[k] * on[k] / count as f64;
+            }
+            if count < 64 {
+                let off = total[k] - on[k];
+                score += off * off / (64 - count) as f64;
+            }
+        }
+        if best.is_none() || score - best_score > best_score.abs() * 1e-9 {
+            best_score = score;
+            best = Some(i);
+        }
+    }
+    best
+}
+
+/* grey level from which a pixel of the cell belongs to the mask */
+fn cell_threshold(grey_scales: &[usize; 64], threshold: Threshold) -> usize {
+    if threshold == Threshold::Mean {
+        return grey_scales.iter().sum::<usize>() / 64;
+    }
+    let mut sorted = *grey_scales;
+    sorted.sort_unstable();
+    match threshold {
+        Threshold::Median => {
+            /* the darkest pixels of a cell which is not uniform stay out of the mask */
+            let median = sorted[32];
+            if median == sorted[0] {
+                sorted
+                    .iter()
+                    .find(|grey| **grey > median)
+                    .copied()
+                    .unwrap_or(median)
+            } else {
+                median
+            }
+        }
+        Threshold::Midpoint => (sorted[0] + sorted[63]).div_ceil(2),
+        _ => {
+            let total: usize = sorted.iter().sum();
+            let mut best = sorted[63];
+            let mut best_variance = 0.0;
+            let mut below = 0;
+            for k in 1..64 {
+                below += sorted[k - 1];
+                if sorted[k] == sorted[k - 1] {
+                    continue;
+                }
+                let dark = below as f64 / k as f64;
+                let bright = (total - below) as f64 / (64 - k) as f64;
+                let variance = (k * (64 - k)) as f64 * (bright - dark) * (bright - dark);
+                if variance > best_variance {
+                    best_variance = variance;
+                    best = sorted[k];
+                }
+            }
+            best
+        }
+    }
+}
+
+fn linear_to_srgb(l: f32) -> u8 {
+    let c = if l <= 0.0031308 {
+        l * 12.92
+    } else {
+        1.055 * l.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+#[inline(always)]
+fn grey_scale(block: (u8, u8, u8, u8), luminance: Luminance) -> usize {
+    if block.3 == 0 {
+        0
+    } else if luminance == Luminance::Rec709 {
+        ((0.2126 * SRGB_TO_LINEAR[block.0 as usize]
+            + 0.7152 * SRGB_TO_LINEAR[block.1 as usize]
+            + 0.0722 * SRGB_TO_LINEAR[block.2 as usize])
+            * 65535.0) as usize
+    } else {
+        block.0 as usize + block.1 as usize + block.2 as usize
+    }
+}
+
+#[inline(always)]
+fn mix(a: (u8, u8, u8, u8), b: (u8, u8, u8, u8), linear: bool) -> (u8, u8, u8, u8) {
+    let channel = |a: u8, b: u8| {
+        if linear {
+            linear_to_srgb((SRGB_TO_LINEAR[a as usize] + SRGB_TO_LINEAR[b as usize]) / 2.0)
+        } else {
+            ((a as u16 + b as u16) / 2) as u8
+        }
+    };
+    (
+        channel(a.0, b.0),
+        channel(a.1, b.1),
+        channel(a.2, b.2),
+        ((a.3 as u16 + b.3 as u16) / 2) as u8,
+    )
+}
+
+#[inline(always)]
+fn color_distance(a: (u8, u8, u8, u8), b: (u8, u8, u8, u8)) -> i32 {
+    let r = a.0 as i32 - b.0 as i32;
+    let g = a.1 as i32 - b.1 as i32;
+    let b = a.2 as i32 - b.2 as i32;
+    r * r + g * g + b * b
+}
+
+/* (r, g, b, a), as read from a pixel source */
+type Color = (u8, u8, u8, u8);
+
+/* splits the cell pixels in two clusters starting from the darkest and brightest ones,
+ * returns the mask of the bright cluster and the averages of both clusters */
+fn two_means(blocks: &[Color; 64], grey_scales: &[usize; 64], linear: bool) -> (u64, Color, Color) {
+    let mut darkest = 0;
+    let mut brightest = 0;
+    for (i, grey) in grey_scales.iter().enumerate() {
+        if *grey < grey_scales[darkest] {
+            darkest = i;
+        }
+        if *grey > grey_scales[brightest] {
+            brightest = i;
+        }
+    }
+    let mut bright = blocks[brightest];
+    let mut dark = blocks[darkest];
+    let mut group = 0;
+    for _ in 0..8 {
+        let mut next = 0;
+        for block in blocks.iter() {
+            next = next << 1
+                | (if color_distance(*block, bright) <= color_distance(*block, dark) {
+                    1
+                } else {
+                    0
+                });
+        }
+        bright = average_color(blocks, next, true, linear);
+        dark = average_color(blocks, next, false, linear);
+        if next == group {
+            break;
+        }
+        group = next;
+    }
+    (group, bright, dark)
+}
+
+fn average_color(blocks: &[Color; 64], mask: u64, on: bool, linear: bool) -> Color {
+    let mut sum = (0.0, 0.0, 0.0, 0);
+    let mut count = 0;
+    for (i, block) in blocks.iter().enumerate() {
+        if mask_contains(mask, i) == on {
+            if linear {
+                sum.0 += SRGB_TO_LINEAR[block.0 as usize];
+                sum.1 += SRGB_TO_LINEAR[block.1 as usize];
+                sum.2 += SRGB_TO_LINEAR[block.2 as usize];
+            } else {
+                sum.0 += block.0 as f32;
+                sum.1 += block.1 as f32;
+                sum.2 += block.2 as f32;
+            }
+            sum.3 += block.3 as usize;
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return average_color(blocks, mask, !on, linear);
+    }
+    let channel = |sum: f32| {
+        if linear {
+            linear_to_srgb(sum / count as f32)
+        } else {
+            (sum / count as f32) as u8
+        }
+    };
+    (
+        channel(sum.0),
+        channel(sum.1),
+        channel(sum.2),
+        (sum.3 / count) as u8,
+    )
+}
+
 pub fn current_terminal_is_supported() -> bool {
-    !cfg!(windows)
+    Capabilities::detect().ansi
 }
 
 pub fn render(
@@ -72,398 +841,341 @@ pub fn render(
     height: u32,
     coordinate_to_rgba: &dyn Fn(u32, u32) -> (u8, u8, u8, u8),
     pos: Option<(u32, u32)>,
-) {
+) -> Result<(), Error> {
     render_write_eol(width, height, coordinate_to_rgba, true, pos)
 }
 
+pub fn render_with_settings(
+    width: u32,
+    height: u32,
+    coordinate_to_rgba: &dyn Fn(u32, u32) -> (u8, u8, u8, u8),
+    pos: Option<(u32, u32)>,
+    settings: &RenderSettings,
+) -> Result<(), Error> {
+    Renderer::new(width, height)
+        .pos(pos)
+        .settings(settings.clone())
+        .render(coordinate_to_rgba)
+}
+
 lazy_static! {
-    static ref TRANSFORMS: HashMap<u64, (bool, char)> = {
-        let mut transforms = HashMap::new();
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8 * 3) + &"1".repeat(8) + &"0".repeat(8 * 4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '─'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(4) + &"1".repeat(8) + &"0".repeat(8).repeat(3)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '─'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(3) + &"1".repeat(8).repeat(2) + &"0".repeat(8).repeat(3))
-                    .as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '━'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("00010000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '│'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("00001000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '│'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("00011000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '┃'),
-        );
+    /* ordered from the simplest glyph to the most detailed one */
+    static ref TRANSFORMS: Vec<(u64, (bool, char))> = {
+        let rows = GlyphSet::mask_from_rows;
+        let mut transforms = GlyphSet::new();
+        transforms.insert(!0, '█', false);
+        transforms.insert(rows([0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]), '▀', true);
+        transforms.insert(rows([0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]), '▄', true);
+        transforms.insert(rows([0xf0; 8]), '▌', false);
+        transforms.insert(rows([0x0f; 8]), '▐', false);
 
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(3) + &"0".repeat(8) + &"1".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '─'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(4) + &"0".repeat(8) + &"1".repeat(8).repeat(3)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '─'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(3) + &"0".repeat(8).repeat(2) + &"1".repeat(8).repeat(3))
-                    .as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '━'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11101111".repeat(8)).as_str(), 2).unwrap(),
-            (false, '│'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11110111".repeat(8)).as_str(), 2).unwrap(),
-            (false, '│'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11100111".repeat(8)).as_str(), 2).unwrap(),
-            (false, '┃'),
-        );
+        transforms.insert(rows([0, 0, 0, 0, 0, 0, 0, 0xff]), '▁', true);
+        transforms.insert(rows([0, 0, 0, 0, 0, 0, 0xff, 0xff]), '▂', true);
+        transforms.insert(rows([0, 0, 0, 0, 0, 0xff, 0xff, 0xff]), '▃', true);
+        transforms.insert(rows([0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff]), '▅', true);
+        transforms.insert(rows([0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), '▆', true);
+        transforms.insert(rows([0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), '▇', true);
+        transforms.insert(rows([0xff, 0, 0, 0, 0, 0, 0, 0]), '▔', false);
 
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(4) + &"0".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▀'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("0".repeat(8).repeat(7) + &"1".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▁'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(6) + &"1".repeat(8).repeat(2)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▂'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(5) + &"1".repeat(8).repeat(3)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▃'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(4) + &"1".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▄'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(3) + &"1".repeat(8).repeat(5)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▅'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(2) + &"1".repeat(8).repeat(6)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▆'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(1) + &"1".repeat(8).repeat(7)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▇'),
-        );
+        transforms.insert(rows([0xfe; 8]), '▉', false);
+        transforms.insert(rows([0xfc; 8]), '▊', false);
+        transforms.insert(rows([0xf8; 8]), '▋', false);
+        transforms.insert(rows([0xe0; 8]), '▍', false);
+        transforms.insert(rows([0xc0; 8]), '▎', false);
+        transforms.insert(rows([0x80; 8]), '▏', false);
+        transforms.insert(rows([0x01; 8]), '▕', false);
 
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(4) + &"1".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▀'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("1".repeat(8).repeat(7) + &"0".repeat(8)).as_str(), 2).unwrap(),
-            (false, '▁'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(6) + &"0".repeat(8).repeat(2)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▂'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(5) + &"0".repeat(8).repeat(3)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▃'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(4) + &"0".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▄'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(3) + &"0".repeat(8).repeat(5)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▅'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(2) + &"0".repeat(8).repeat(6)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▆'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(1) + &"0".repeat(8).repeat(7)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (false, '▇'),
-        );
+        transforms.insert(rows([0xf0, 0xf0, 0xf0, 0xf0, 0, 0, 0, 0]), '▘', false);
+        transforms.insert(rows([0x0f, 0x0f, 0x0f, 0x0f, 0, 0, 0, 0]), '▝', false);
+        transforms.insert(rows([0, 0, 0, 0, 0xf0, 0xf0, 0xf0, 0xf0]), '▖', false);
+        transforms.insert(rows([0, 0, 0, 0, 0x0f, 0x0f, 0x0f, 0x0f]), '▗', false);
+        transforms.insert(rows([0xf0, 0xf0, 0xf0, 0xf0, 0x0f, 0x0f, 0x0f, 0x0f]), '▚', false);
+        transforms.insert(rows([0x0f, 0x0f, 0x0f, 0x0f, 0xf0, 0xf0, 0xf0, 0xf0]), '▞', false);
+        transforms.insert(rows([0xf0, 0xf0, 0xf0, 0xf0, 0xff, 0xff, 0xff, 0xff]), '▙', false);
+        transforms.insert(rows([0xff, 0xff, 0xff, 0xff, 0xf0, 0xf0, 0xf0, 0xf0]), '▛', false);
+        transforms.insert(rows([0xff, 0xff, 0xff, 0xff, 0x0f, 0x0f, 0x0f, 0x0f]), '▜', false);
+        transforms.insert(rows([0x0f, 0x0f, 0x0f, 0x0f, 0xff, 0xff, 0xff, 0xff]), '▟', false);
 
-        transforms.insert(
-            u64::from_str_radix(&("1".repeat(8).repeat(8)).as_str(), 2).unwrap(),
-            (true, '█'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11111110".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▉'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11111100".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▊'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11111000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▋'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11110000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▌'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11100000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▍'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("11000000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▎'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("10000000".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▏'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("00001111".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▐'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&(("1000100000100010").repeat(4)).as_str(), 2).unwrap(),
-            (true, '░'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&(("1010101001010100").repeat(4)).as_str(), 2).unwrap(),
-            (true, '▒'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&(("0111011111011101").repeat(4)).as_str(), 2).unwrap(),
-            (true, '▓'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("1".repeat(8) + &"0".repeat(8).repeat(7)).as_str(), 2).unwrap(),
-            (true, '▔'),
-        );
-        transforms.insert(
-            u64::from_str_radix(&("00000001".repeat(8)).as_str(), 2).unwrap(),
-            (true, '▕'),
-        );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(4) + &"11110000".repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▖'),
+        transforms.insert(rows([0, 0, 0, 0xff, 0, 0, 0, 0]), '─', true);
+        transforms.insert(rows([0, 0, 0, 0, 0xff, 0, 0, 0]), '─', true);
+        transforms.insert(rows([0, 0, 0, 0xff, 0xff, 0, 0, 0]), '━', true);
+        transforms.insert(rows([0x10; 8]), '│', true);
+        transforms.insert(rows([0x08; 8]), '│', true);
+        transforms.insert(rows([0x18; 8]), '┃', true);
+
+        transforms.insert(rows([0x88, 0x22, 0x88, 0x22, 0x88, 0x22, 0x88, 0x22]), '░', false);
+        transforms.insert(rows([0xaa, 0x54, 0xaa, 0x54, 0xaa, 0x54, 0xaa, 0x54]), '▒', false);
+        transforms.insert(rows([0x77, 0xdd, 0x77, 0xdd, 0x77, 0xdd, 0x77, 0xdd]), '▓', false);
+        transforms.glyphs
+    };
+}
+
+fn mask_from_cells(cell: &dyn Fn(usize, usize) -> usize, cells: u32) -> u64 {
+    let mut group = 0;
+    for dy in 0..8 {
+        for dx in 0..8 {
+            group = group << 1 | ((cells >> cell(dx, dy)) & 1) as u64;
+        }
+    }
+    group
+}
+
+lazy_static! {
+    /* sextants split the cell in 2 columns and 3 rows, numbered left to right
+     * then top to bottom; the left and right halves are not encoded */
+    static ref SEXTANTS: Vec<(u64, (bool, char))> = {
+        let cell = |dx: usize, dy: usize| (if dy < 3 { 0 } else if dy < 6 { 2 } else { 4 }) + dx / 4;
+        let mut sextants = Vec::new();
+        let mut code = 0x1fb00;
+        for cells in 1..63u32 {
+            if cells == 0b010101 || cells == 0b101010 {
+                continue;
+            }
+            if let Some(c) = std::char::from_u32(code) {
+                sextants.push((mask_from_cells(&cell, cells), (true, c)));
+            }
+            code += 1;
+        }
+        sextants
+    };
+    /* octants split the cell in 2 columns and 4 rows, numbered like sextants;
+     * patterns already encoded as block elements are skipped */
+    static ref OCTANTS: Vec<(u64, (bool, char))> = {
+        let cell = |dx: usize, dy: usize| (dy / 2) * 2 + dx / 4;
+        let encoded_elsewhere = [
+            0x00, 0x01, 0x02, 0x03, 0x05, 0x0a, 0x0f, 0x14, 0x28, 0x3f, 0x40, 0x50, 0x55,
+            0x5a, 0x5f, 0x80, 0xa0, 0xa5, 0xaa, 0xaf, 0xc0, 0xf0, 0xf5, 0xfa, 0xfc, 0xff,
+        ];
+        let mut octants = Vec::new();
+        let mut code = 0x1cd00;
+        for cells in 0..256u32 {
+            if encoded_elsewhere.contains(&cells) {
+                continue;
+            }
+            if let Some(c) = std::char::from_u32(code) {
+                octants.push((mask_from_cells(&cell, cells), (true, c)));
+            }
+            code += 1;
+        }
+        octants
+    };
+}
+
+/* a glyph is matched against the 8x8 mask of the pixels brighter than the cell
+ * average, the most significant bit being the top left pixel.
+ * inverse glyphs are drawn with foreground and background swapped,
+ * so they stand for the complement of their mask.
+ * glyphs are tried in insertion order and, when several of them are
+ * as close to a cell, the first one wins: insert simpler glyphs first */
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlyphSet {
+    glyphs: Vec<(u64, (bool, char))>,
+}
+
+impl GlyphSet {
+    pub fn new() -> GlyphSet {
+        GlyphSet { glyphs: Vec::new() }
+    }
+
+    pub fn mask_from_rows(rows: [u8; 8]) -> u64 {
+        let mut mask = 0;
+        for row in &rows {
+            mask = mask << 8 | *row as u64;
+        }
+        mask
+    }
+
+    pub fn insert(&mut self, mask: u64, character: char, inverse: bool) {
+        self.glyphs.push((mask, (true, character)));
+        if inverse {
+            self.glyphs.push((!mask, (false, character)));
+        }
+    }
+
+    pub fn with(mut self, other: &GlyphSet) -> GlyphSet {
+        self.glyphs.extend(other.glyphs.iter());
+        self
+    }
+
+    pub fn without(mut self, characters: &str) -> GlyphSet {
+        self.glyphs.retain(|(_, (_, c))| !characters.contains(*c));
+        self
+    }
+
+    pub fn characters(&self) -> Vec<char> {
+        let mut characters: Vec<char> = Vec::new();
+        for (_, (_, c)) in &self.glyphs {
+            if !characters.contains(c) {
+                characters.push(*c);
+            }
+        }
+        characters
+    }
+
+    pub fn blocks_and_box() -> GlyphSet {
+        GlyphSet {
+            glyphs: TRANSFORMS.clone(),
+        }
+    }
+
+    pub fn blocks() -> GlyphSet {
+        GlyphSet::blocks_and_box().without("─━│┃")
+    }
+
+    pub fn quadrants() -> GlyphSet {
+        let mut set = GlyphSet::blocks_and_box();
+        set.glyphs
+            .retain(|(_, (_, c))| "▀▄▌▐█▖▗▘▙▚▛▜▝▞▟".contains(*c));
+        set
+    }
+
+    pub fn ascii() -> GlyphSet {
+        let mut set = GlyphSet::new();
+        set.insert(0, ' ', true);
+        set.insert(
+            GlyphSet::mask_from_rows([0, 0, 0, 0, 0, 0, 0, 0xff]),
+            '_',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("0".repeat(8).repeat(4) + &"00001111".repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▗'),
+        set.insert(
+            GlyphSet::mask_from_rows([0, 0, 0, 0xff, 0, 0, 0, 0]),
+            '-',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("11110000".repeat(4) + &"0".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▘'),
+        set.insert(
+            GlyphSet::mask_from_rows([0, 0, 0xff, 0, 0, 0xff, 0, 0]),
+            '=',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("11110000".repeat(4) + &"1".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▙'),
+        set.insert(GlyphSet::mask_from_rows([0x18; 8]), '|', false);
+        set.insert(
+            GlyphSet::mask_from_rows([0x18, 0x18, 0x18, 0xff, 0xff, 0x18, 0x18, 0x18]),
+            '+',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(&("11110000".repeat(4) + &"00001111".repeat(4)).as_str(), 2)
-                .unwrap(),
-            (true, '▚'),
+        set.insert(
+            GlyphSet::mask_from_rows([0x03, 0x06, 0x0c, 0x18, 0x18, 0x30, 0x60, 0xc0]),
+            '/',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(4) + &"11110000".repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▛'),
+        set.insert(
+            GlyphSet::mask_from_rows([0xc0, 0x60, 0x30, 0x18, 0x18, 0x0c, 0x06, 0x03]),
+            '\\',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("1".repeat(8).repeat(4) + &"00001111".repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▜'),
+        set.insert(
+            GlyphSet::mask_from_rows([0, 0, 0, 0, 0, 0, 0x18, 0x18]),
+            '.',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("00001111".repeat(4) + &"0".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▝'),
+        set.insert(
+            GlyphSet::mask_from_rows([0x18, 0x18, 0, 0, 0, 0, 0, 0]),
+            '\'',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(&("00001111".repeat(4) + &"11110000".repeat(4)).as_str(), 2)
-                .unwrap(),
-            (true, '▞'),
+        set.insert(
+            GlyphSet::mask_from_rows([0, 0x18, 0x18, 0, 0, 0x18, 0x18, 0]),
+            ':',
+            false,
         );
-        transforms.insert(
-            u64::from_str_radix(
-                &("00001111".repeat(4) + &"1".repeat(8).repeat(4)).as_str(),
-                2,
-            )
-            .unwrap(),
-            (true, '▟'),
+        set.insert(
+            GlyphSet::mask_from_rows([0x66, 0xff, 0x66, 0x66, 0x66, 0x66, 0xff, 0x66]),
+            '#',
+            false,
         );
-        transforms
-    };
+        set
+    }
+
+    pub fn sextants() -> GlyphSet {
+        GlyphSet {
+            glyphs: SEXTANTS.clone(),
+        }
+    }
+
+    pub fn octants() -> GlyphSet {
+        GlyphSet {
+            glyphs: OCTANTS.clone(),
+        }
+    }
 }
 
-pub fn render_write_eol_with_write_with_restart_start_of_line(
+impl Default for GlyphSet {
+    fn default() -> GlyphSet {
+        GlyphSet::blocks_and_box()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Attributes {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+/* colors are sRGB, an alpha of 0 standing for the default color of the terminal,
+ * they are mapped to the color mode when written */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub character: char,
+    pub fg: (u8, u8, u8, u8),
+    pub bg: (u8, u8, u8, u8),
+    pub attributes: Attributes,
+}
+
+/* cells of the rows from top / 16 to bottom / 16, row by row */
+fn render_band<P: PixelSource + ?Sized>(
     width: u32,
-    coordinate_to_rgba: &dyn Fn(u32, u32) -> (u8, u8, u8, u8),
-    write_eol: bool,
-    restart_start_of_line: bool,
+    source: &P,
     top: u32,
     bottom: u32,
-    handle: &mut dyn Write,
-    pos: Option<(u32, u32)>,
-) {
-    let mut transforms_keys: Vec<u64> = Vec::new();
-    let mut transforms_keys_without_reverse: Vec<u64> = Vec::new();
-    for k in TRANSFORMS.keys() {
-        transforms_keys.push(*k);
-        match TRANSFORMS.get(k) {
-            Some((true, _)) => transforms_keys_without_reverse.push(*k),
-            _ => {}
-        }
+    settings: &RenderSettings,
+) -> Vec<Cell> {
+    let transforms = &settings.glyph_set.glyphs;
+    let mut exact_transforms: HashMap<u64, (bool, char)> = HashMap::new();
+    for (k, v) in transforms {
+        exact_transforms.entry(*k).or_insert(*v);
     }
-    let transforms_keys = transforms_keys.as_slice();
 
+    let mut transforms_keys: Vec<u64> = Vec::new();
+    let mut transforms_keys_without_reverse: Vec<u64> = Vec::new();
     let mut transforms_values: Vec<(bool, char)> = Vec::new();
     let mut transforms_values_without_reverse: Vec<(bool, char)> = Vec::new();
-    for k in TRANSFORMS.values() {
-        transforms_values.push(*k);
-        match k {
-            (true, _) => transforms_values_without_reverse.push(*k),
-            _ => {}
+    for (k, v) in transforms {
+        transforms_keys.push(*k);
+        transforms_values.push(*v);
+        if v.0 {
+            transforms_keys_without_reverse.push(*k);
+            transforms_values_without_reverse.push(*v);
         }
     }
+    let transforms_keys = transforms_keys.as_slice();
     let transforms_values = transforms_values.as_slice();
 
     const AVERAGE_SIZE: usize = 8;
-    let mut sorted: [(usize, usize, (u8, u8, u8, u8)); AVERAGE_SIZE] =
-        [(0, 0, (0, 0, 0, 0)); AVERAGE_SIZE];
-    let mut grey_scales_start: [usize; 32] = [0; 32];
-    let mut grey_scales_end: [usize; 32] = [0; 32];
-    let mut line = 0;
+    let full = settings.sampling == Sampling::Full;
+    let linear = settings.luminance == Luminance::Rec709;
+    let monochrome = settings.color_mode == ColorMode::None || settings.plain;
+    let average_size = if full { 64 } else { AVERAGE_SIZE };
+    let mut sorted: [(usize, usize, Color); 64] = [(0, 0, (0, 0, 0, 0)); 64];
+    let mut grey_scales: [usize; 64] = [0; 64];
+    let mut blocks: [Color; 64] = [(0, 0, 0, 0); 64];
+    let dither = settings.dither != Dither::None
+        && !monochrome
+        && (settings.color_mode == ColorMode::Xterm256 || settings.color_mode == ColorMode::Ansi16);
+    let mut errors = vec![vec![[0.0; 6]; (width / 8) as usize + 4]; 3];
+    let mut cells = Vec::new();
     for y in (top / 16)..(bottom / 16) {
-        let mut line_str = String::new();
-        if restart_start_of_line {
-            line_str.push_str("\x1b[0G");
-        }
-        match pos {
-            Some((x, y)) => {
-                line_str.push_str(format!("\x1b[{};{}H", y + line, x).as_str());
-                line += 1;
+        if dither {
+            errors.rotate_left(1);
+            for error in errors[2].iter_mut() {
+                *error = [0.0; 6];
             }
-            _ => {}
         }
         let mut x = 0;
         while x < (width / 8) {
             let mut sum_grey_scale: usize = 0;
+            let mut transparent = false;
             let mut i = 0;
             let mut dy: usize = 0;
             let mut dx: usize;
@@ -472,82 +1184,467 @@ pub fn render_write_eol_with_write_with_restart_start_of_line(
                 while dx < 8 {
                     let _x = x * 8 + (dx as u32);
                     let _y = y * 16 + (dy as u32) * 2;
-                    let block = coordinate_to_rgba(_x, _y);
-                    // greyscale
-                    let grey = if block.3 == 0 {
-                        0
+                    let block = if full {
+                        mix(source.pixel(_x, _y), source.pixel(_x, _y + 1), linear)
                     } else {
-                        block.0 as usize + block.1 as usize + block.2 as usize
+                        source.pixel(_x, _y)
                     };
-                    /* do not write every pixel in sorted so that sort_by is faster,
-                     * instead only select pixels in the diagonal
-                     * the downside is that this reduce quality a lot */
-                    if i % AVERAGE_SIZE == dy {
+                    // greyscale
+                    let grey = grey_scale(block, settings.luminance);
+                    if full {
+                        sorted[i] = (grey, i, block)
+                    } else if i % AVERAGE_SIZE == dy {
+                        /* with fast sampling, do not write every pixel in sorted so that
+                         * sort_by is faster, instead only select pixels in the diagonal
+                         * the downside is that this reduce quality a lot */
                         sorted[dy] = (grey, i, block)
                     };
-                    if i < 32 {
-                        grey_scales_start[i] = grey;
-                    } else {
-                        grey_scales_end[i - 32] = grey;
-                    }
+                    grey_scales[i] = grey;
+                    blocks[i] = block;
+                    transparent |= block.3 == 0;
                     sum_grey_scale += grey;
                     i += 1;
                     dx += 1;
                 }
                 dy += 1
             }
-            let average_grey_scale: usize = sum_grey_scale / 64;
-            sorted.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-            let average_min = sorted[AVERAGE_SIZE / 4].2;
-            let average_max = sorted[(3 * AVERAGE_SIZE) / 4].2;
+            let average_grey_scale: usize = if settings.threshold == Threshold::Mean {
+                sum_grey_scale / 64
+            } else {
+                cell_threshold(&grey_scales, settings.threshold)
+            };
+            let sorted = &mut sorted[..average_size];
+            sorted.sort_unstable_by_key(|sample| (sample.0, sample.1));
+            let mut average_min = sorted[average_size / 4].2;
+            let mut average_max = sorted[(3 * average_size) / 4].2;
             let mut group = 0;
-            for grey in &grey_scales_start {
-                group = group << 1 | (if grey >= &average_grey_scale { 1 } else { 0 });
-            }
-            for grey in &grey_scales_end {
-                group = group << 1 | (if grey >= &average_grey_scale { 1 } else { 0 });
-            }
-            let no_transparency = average_max.3 != 0 && average_min.3 != 0;
-            let transform = match TRANSFORMS.get(&group) {
-                Some(t) => t,
-                _ => {
-                    if no_transparency {
-                        match find_closest_group(&transforms_keys, group) {
-                            Some(x) => &transforms_values[x],
-                            _ => &(true, ' '),
-                        }
+            if settings.color_selection == ColorSelection::KMeans && !transparent {
+                let (mask, bright, dark) = two_means(&blocks, &grey_scales, linear);
+                group = mask;
+                average_max = bright;
+                average_min = dark;
+            } else {
+                for grey in &grey_scales {
+                    group = group << 1 | (if grey >= &average_grey_scale { 1 } else { 0 });
+                }
+            }
+            let (fg, bg, result) = if settings.mode == Mode::Braille {
+                (average_max, average_min, braille(group))
+            } else if settings.matcher != Matcher::Hamming && !transparent && !monochrome {
+                let mut colors = [[0.0; 3]; 64];
+                for (color, block) in colors.iter_mut().zip(blocks.iter()) {
+                    *color = if settings.matcher == Matcher::OklabError {
+                        oklab(*block)
                     } else {
-                        match find_closest_group(&transforms_keys_without_reverse, group) {
-                            Some(x) => &transforms_values_without_reverse[x],
-                            _ => &(true, ' '),
+                        [block.0 as f32, block.1 as f32, block.2 as f32]
+                    };
+                }
+                match find_lowest_color_error(transforms_keys, &colors) {
+                    Some(x) => {
+                        let on = average_color(&blocks, transforms_keys[x], true, linear);
+                        let off = average_color(&blocks, transforms_keys[x], false, linear);
+                        let transform = transforms_values[x];
+                        if transform.0 {
+                            (on, off, transform.1)
+                        } else {
+                            (off, on, transform.1)
                         }
                     }
+                    _ => (average_max, average_min, ' '),
                 }
-            };
-            let (fg, bg) = if transform.0 {
-                (average_max, average_min)
             } else {
-                (average_min, average_max)
+                /* inverse glyphs need a background, and without colors
+                 * the glyphs must draw the bright part of the cell */
+                let no_transparency = average_max.3 != 0 && average_min.3 != 0 && !monochrome;
+                let transform = match exact_transforms.get(&group) {
+                    Some(t) if t.0 || no_transparency => t,
+                    _ => {
+                        if no_transparency {
+                            match find_closest_group(&transforms_keys, group) {
+                                Some(x) => &transforms_values[x],
+                                _ => &(true, ' '),
+                            }
+                        } else {
+                            match find_closest_group(&transforms_keys_without_reverse, group) {
+                                Some(x) => &transforms_values_without_reverse[x],
+                                _ => &(true, ' '),
+                            }
+                        }
+                    }
+                };
+                if transform.0 {
+                    (average_max, average_min, transform.1)
+                } else {
+                    (average_min, average_max, transform.1)
+                }
             };
-            let result = transform.1;
-            if fg.3 != 0 {
-                line_str.push_str(format!("\x1b[38;2;{};{};{}m", fg.0, fg.1, fg.2).as_str());
+            let (fg, bg) = if dither {
+                (
+                    if fg.3 != 0 {
+                        dither_color(fg, settings, &mut errors, x as usize, y as usize, 0)
+                    } else {
+                        fg
+                    },
+                    if bg.3 != 0 {
+                        dither_color(bg, settings, &mut errors, x as usize, y as usize, 3)
+                    } else {
+                        bg
+                    },
+                )
             } else {
-                line_str.push_str("\x1b[0m");
-            }
-            if bg.3 != 0 {
-                line_str.push_str(format!("\x1b[48;2;{};{};{}m", bg.0, bg.1, bg.2).as_str());
-            }
-            line_str.push_str(format!("{}", if fg.3 == 0 { ' ' } else { result }).as_str());
+                (fg, bg)
+            };
+            cells.push(Cell {
+                character: if fg.3 == 0 { ' ' } else { result },
+                fg,
+                bg,
+                attributes: Attributes::default(),
+            });
             x += 1;
         }
+    }
+    cells
+}
+
+/* a cell covers 8 x 16 pixels, the grid has width / 8 columns and height / 16 rows */
+pub fn render_cells<P: PixelSource + ?Sized>(
+    width: u32,
+    height: u32,
+    source: &P,
+    settings: &RenderSettings,
+) -> Vec<Cell> {
+    render_band(width, source, 0, height, settings)
+}
+
+fn write_cells(
+    cells: &[Cell],
+    columns: u32,
+    write_eol: bool,
+    restart_start_of_line: bool,
+    handle: &mut dyn Write,
+    pos: Option<(u32, u32)>,
+    settings: &RenderSettings,
+) -> Result<(), Error> {
+    let mut sgr = Sgr::new(settings);
+    let mut line = 0;
+    for row in cells.chunks(columns.max(1) as usize) {
+        let mut line_str = String::new();
+        if restart_start_of_line && !settings.plain {
+            line_str.push_str("\x1b[0G");
+        }
+        match pos {
+            Some((x, y)) if !settings.plain => {
+                line_str.push_str(format!("\x1b[{};{}H", y + line, x).as_str());
+                line += 1;
+            }
+            _ => {}
+        }
+        for cell in row {
+            sgr.cell(&mut line_str, cell);
+        }
         if write_eol {
-            line_str.push_str("\x1b[0m\n");
+            sgr.reset(&mut line_str);
+            line_str.push('\n');
+        } else {
+            sgr.flush(&mut line_str);
+        }
+        write!(handle, "{}", line_str)?;
+    }
+    Ok(())
+}
+
+/* writes the cells which differ from the previous frame, moving the cursor
+ * relatively to where the frame starts, and leaves the cursor where
+ * writing the whole frame would */
+fn write_cells_diff(
+    cells: &[Cell],
+    previous: &[Cell],
+    columns: u32,
+    write_eol: bool,
+    handle: &mut dyn Write,
+    settings: &RenderSettings,
+) -> io::Result<()> {
+    let mut out = String::new();
+    let mut sgr = Sgr::new(settings);
+    let mut line = 0;
+    let columns = columns as usize;
+    let rows = cells.len() / columns;
+    for (y, (row, previous_row)) in cells
+        .chunks(columns)
+        .zip(previous.chunks(columns))
+        .enumerate()
+    {
+        let mut cursor = None;
+        for (x, (cell, previous_cell)) in row.iter().zip(previous_row.iter()).enumerate() {
+            if cell == previous_cell {
+                continue;
+            }
+            if cursor != Some(x) {
+                sgr.flush(&mut out);
+                if y > line {
+                    out.push_str(format!("\x1b[{}B", y - line).as_str());
+                    line = y;
+                }
+                out.push_str(format!("\x1b[{}G", x + 1).as_str());
+            }
+            sgr.cell(&mut out, cell);
+            cursor = Some(x + 1);
+        }
+    }
+    sgr.flush(&mut out);
+    if rows > line + 1 {
+        out.push_str(format!("\x1b[{}B", rows - line - 1).as_str());
+    }
+    if write_eol {
+        sgr.reset(&mut out);
+        out.push('\n');
+    } else {
+        out.push_str(format!("\x1b[{}G", columns + 1).as_str());
+    }
+    write!(handle, "{}", out)
+}
+
+/* renders width x height pixels to stdout, each line of cells followed by a new line,
+ * with the settings detected for the current terminal */
+pub struct Renderer<'a> {
+    width: u32,
+    height: u32,
+    band: Option<(u32, u32)>,
+    write_eol: bool,
+    restart_start_of_line: bool,
+    pos: Option<(u32, u32)>,
+    settings: RenderSettings,
+    sink: Option<&'a mut dyn Write>,
+    image_id: Option<u32>,
+    cell_size: Option<(u32, u32)>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(width: u32, height: u32) -> Renderer<'a> {
+        let settings = RenderSettings::detect();
+        Renderer {
+            width,
+            height,
+            band: None,
+            write_eol: true,
+            /* the cursor can only be moved back when the terminal understands it */
+            restart_start_of_line: !settings.plain,
+            pos: None,
+            settings,
+            sink: None,
+            image_id: None,
+            cell_size: None,
+        }
+    }
+
+    /* only the cells from the row top / 16 to the row bottom / 16 */
+    pub fn band(mut self, top: u32, bottom: u32) -> Renderer<'a> {
+        self.band = Some((top, bottom));
+        self
+    }
+
+    pub fn write_eol(mut self, write_eol: bool) -> Renderer<'a> {
+        self.write_eol = write_eol;
+        self
+    }
+
+    pub fn restart_start_of_line(mut self, restart_start_of_line: bool) -> Renderer<'a> {
+        self.restart_start_of_line = restart_start_of_line;
+        self
+    }
+
+    /* column and row of the terminal, starting from 1, where the image is drawn */
+    pub fn pos(mut self, pos: Option<(u32, u32)>) -> Renderer<'a> {
+        self.pos = pos;
+        self
+    }
+
+    pub fn settings(mut self, settings: RenderSettings) -> Renderer<'a> {
+        self.settings = settings;
+        self
+    }
+
+    pub fn glyph_set(mut self, glyph_set: GlyphSet) -> Renderer<'a> {
+        self.settings.glyph_set = glyph_set;
+        self
+    }
+
+    pub fn color_mode(mut self, color_mode: ColorMode) -> Renderer<'a> {
+        self.settings.color_mode = color_mode;
+        self
+    }
+
+    pub fn sink(mut self, sink: &'a mut dyn Write) -> Renderer<'a> {
+        self.sink = Some(sink);
+        self
+    }
+
+    /* with the kitty backend, the image replaces the previous one drawn with this id */
+    pub fn image_id(mut self, image_id: Option<u32>) -> Renderer<'a> {
+        self.image_id = image_id;
+        self
+    }
+
+    /* with the sixel backend, the width and height of a cell in pixels,
+     * by default the ones the terminal answers, or 8 x 16 */
+    pub fn cell_size(mut self, cell_size: Option<(u32, u32)>) -> Renderer<'a> {
+        self.cell_size = cell_size;
+        self
+    }
+
+    pub fn cells<P: PixelSource + ?Sized>(&self, source: &P) -> Vec<Cell> {
+        let (top, bottom) = self.band.unwrap_or((0, self.height));
+        render_band(self.width, source, top, bottom, &self.settings)
+    }
+
+    /* writes cells rendered before, e.g. by cells, where and as the renderer would */
+    pub fn write_cells(&mut self, cells: &[Cell]) -> Result<(), Error> {
+        let stdout = io::stdout();
+        let mut stdout_handle;
+        let handle: &mut dyn Write = match self.sink {
+            Some(ref mut sink) => *sink,
+            None => {
+                stdout_handle = stdout.lock();
+                &mut stdout_handle
+            }
+        };
+        match self.settings.backend {
+            Backend::Html { standalone } => html::write_html(
+                cells,
+                self.width / 8,
+                self.settings.color_mode,
+                standalone,
+                handle,
+            )?,
+            _ => write_cells(
+                cells,
+                self.width / 8,
+                self.write_eol,
+                self.restart_start_of_line,
+                handle,
+                self.pos,
+                &self.settings,
+            )?,
+        }
+        handle.flush()?;
+        Ok(())
+    }
+
+    /* renders the cells with the threads of the pool, the graphics protocols
+     * are written as with render */
+    pub fn render_thread_pool<P: PixelSource + Sync + ?Sized>(
+        &mut self,
+        source: &P,
+        pool: &mut Pool,
+    ) -> Result<(), Error> {
+        if let Backend::Cells | Backend::Html { .. } = self.settings.backend {
+            let (top, bottom) = self.band.unwrap_or((0, self.height));
+            let cells =
+                render_cells_thread_pool(self.width, top, bottom, source, pool, &self.settings);
+            return self.write_cells(&cells);
+        }
+        self.render(source)
+    }
+
+    pub fn render<P: PixelSource + ?Sized>(&mut self, source: &P) -> Result<(), Error> {
+        if let Backend::Cells | Backend::Html { .. } = self.settings.backend {
+            let cells = self.cells(source);
+            return self.write_cells(&cells);
+        }
+        let stdout = io::stdout();
+        let mut stdout_handle;
+        let handle: &mut dyn Write = match self.sink {
+            Some(ref mut sink) => *sink,
+            None => {
+                stdout_handle = stdout.lock();
+                &mut stdout_handle
+            }
+        };
+        let (top, bottom) = self.band.unwrap_or((0, self.height));
+        let plain = self.settings.plain;
+        let mut start = String::new();
+        if self.restart_start_of_line && !plain {
+            start.push_str("\x1b[0G");
+        }
+        if let Some((x, y)) = self.pos.filter(|_| !plain) {
+            start.push_str(format!("\x1b[{};{}H", y, x).as_str());
         }
-        write!(handle, "{}", line_str).unwrap();
+        /* the pixels of whole cells, drawn over the cells the characters would have been in */
+        let width = self.width / 8 * 8;
+        let (top, bottom) = (top / 16 * 16, bottom / 16 * 16);
+        match self.settings.backend {
+            /* written as cells above */
+            Backend::Cells | Backend::Html { .. } => {}
+            Backend::Sixel => {
+                write!(handle, "{}", start)?;
+                /* terminals do not scale sixel images, so the pixels are resampled
+                 * to the size of the cells */
+                let (cell_width, cell_height) = self
+                    .cell_size
+                    .or_else(|| Capabilities::detect_with_queries().cell_size)
+                    .filter(|size| size.0 > 0 && size.1 > 0)
+                    .unwrap_or((8, 16));
+                let scaled =
+                    |x: u32, y: u32| source.pixel(x * 8 / cell_width, top + y * 16 / cell_height);
+                sixel::write_sixel(
+                    width / 8 * cell_width,
+                    0,
+                    (bottom - top) / 16 * cell_height,
+                    &scaled,
+                    handle,
+                )?;
+            }
+            Backend::Kitty => {
+                write!(handle, "{}", start)?;
+                let rgba = pixels::read_rgba(source, width, top, bottom);
+                kitty::write_kitty(
+                    &rgba,
+                    width,
+                    bottom - top,
+                    (width / 8, (bottom - top) / 16),
+                    self.image_id,
+                    kitty::Payload::Rgba,
+                    handle,
+                )?;
+            }
+            Backend::Iterm => {
+                write!(handle, "{}", start)?;
+                let rgba = pixels::read_rgba(source, width, top, bottom);
+                iterm::write_iterm(
+                    &rgba,
+                    width,
+                    bottom - top,
+                    (width / 8, (bottom - top) / 16),
+                    handle,
+                )?;
+            }
+        }
+        if self.write_eol {
+            writeln!(handle)?;
+        }
+        handle.flush()?;
+        Ok(())
     }
-    handle.write(&[0]).unwrap();
-    let _ = handle.flush();
+}
+
+pub fn render_write_eol_with_write_with_restart_start_of_line(
+    width: u32,
+    coordinate_to_rgba: &dyn Fn(u32, u32) -> (u8, u8, u8, u8),
+    write_eol: bool,
+    restart_start_of_line: bool,
+    top: u32,
+    bottom: u32,
+    handle: &mut dyn Write,
+    pos: Option<(u32, u32)>,
+) -> Result<(), Error> {
+    Renderer::new(width, bottom)
+        .band(top, bottom)
+        .write_eol(write_eol)
+        .restart_start_of_line(restart_start_of_line)
+        .pos(pos)
+        .settings(RenderSettings::default())
+        .sink(handle)
+        .render(coordinate_to_rgba)
 }
 
 pub fn render_write_eol_with_write(
@@ -558,17 +1655,15 @@ pub fn render_write_eol_with_write(
     bottom: u32,
     handle: &mut dyn Write,
     pos: Option<(u32, u32)>,
-) {
-    render_write_eol_with_write_with_restart_start_of_line(
-        width,
-        coordinate_to_rgba,
-        write_eol,
-        true,
-        top,
-        bottom,
-        handle,
-        pos,
-    );
+) -> Result<(), Error> {
+    Renderer::new(width, bottom)
+        .band(top, bottom)
+        .write_eol(write_eol)
+        .restart_start_of_line(true)
+        .pos(pos)
+        .settings(RenderSettings::default())
+        .sink(handle)
+        .render(coordinate_to_rgba)
 }
 
 pub fn render_write_eol(
@@ -577,19 +1672,15 @@ pub fn render_write_eol(
     coordinate_to_rgba: &dyn Fn(u32, u32) -> (u8, u8, u8, u8),
     write_eol: bool,
     pos: Option<(u32, u32)>,
-) {
-    let mut stdout = io::stdout();
-    render_write_eol_with_write(
-        width,
-        coordinate_to_rgba,
-        write_eol,
-        0,
-        height,
-        &mut stdout,
-        pos,
-    );
+) -> Result<(), Error> {
+    Renderer::new(width, height)
+        .write_eol(write_eol)
+        .pos(pos)
+        .render(coordinate_to_rgba)
 }
 
+/* returns the number of bytes written at the start of the buffer,
+ * a Renderer with a Cursor over the buffer as sink takes other settings */
 pub fn render_write_eol_relative_buffer(
     width: u32,
     coordinate_to_rgba: &dyn Fn(u32, u32) -> (u8, u8, u8, u8),
@@ -597,18 +1688,16 @@ pub fn render_write_eol_relative_buffer(
     top: u32,
     bottom: u32,
     buffer: &mut [u8],
-) -> u64 {
+) -> Result<u64, Error> {
     let mut handle = Cursor::new(buffer);
-    render_write_eol_with_write(
-        width,
-        coordinate_to_rgba,
-        write_eol,
-        top,
-        bottom,
-        &mut handle,
-        None,
-    );
-    handle.position()
+    Renderer::new(width, bottom)
+        .band(top, bottom)
+        .write_eol(write_eol)
+        .restart_start_of_line(true)
+        .settings(RenderSettings::default())
+        .sink(&mut handle)
+        .render(coordinate_to_rgba)?;
+    Ok(handle.position())
 }
 
 pub fn render_thread_pool(
@@ -618,7 +1707,7 @@ pub fn render_thread_pool(
     write_eol: bool,
     pool: &mut Pool,
     output_buffers: &mut Vec<Vec<u8>>,
-) {
+) -> Result<(), Error> {
     let n = pool.thread_count();
     let mut k = 0;
     pool.scoped(|scope| {
@@ -635,101 +1724,775 @@ pub fn render_thread_pool(
                         i * height / n,
                         (i + 1) * height / n,
                         output_buffer.as_mut_slice(),
-                    ) as usize;
+                    );
                     let _ = tx.send((i, pos, output_buffer));
                 });
             }
             k += 1
         }
+        /* so that receiving fails instead of blocking if a thread never sends */
+        drop(tx);
         let mut result = Vec::new();
         for _ in 0..n {
-            result.push(rx.recv().unwrap());
+            result.push(rx.recv().map_err(|_| Error::Thread)?);
         }
         result.sort_by_key(|k| k.0);
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
         for (_, pos, output_buffer) in result {
+            let pos = pos? as usize;
             if let Ok(s) = str::from_utf8(&output_buffer[0..pos]) {
-                print!("{}", s);
+                write!(handle, "{}", s)?;
             }
         }
+        handle.flush()?;
+        Ok(())
     })
 }
 
+/* the cells of the rows from top / 16 to bottom / 16, each thread rendering a band */
+fn render_cells_thread_pool<P: PixelSource + Sync + ?Sized>(
+    width: u32,
+    top: u32,
+    bottom: u32,
+    source: &P,
+    pool: &mut Pool,
+    settings: &RenderSettings,
+) -> Vec<Cell> {
+    let n = pool.thread_count();
+    let height = bottom.saturating_sub(top);
+    let mut bands = vec![Vec::new(); n as usize];
+    pool.scoped(|scope| {
+        for (i, band) in bands.iter_mut().enumerate() {
+            let i = i as u32;
+            scope.execute(move || {
+                *band = render_band(
+                    width,
+                    source,
+                    top + i * height / n,
+                    top + (i + 1) * height / n,
+                    settings,
+                );
+            });
+        }
+    });
+    bands.concat()
+}
+
 pub struct ThreadedEngine {
     width: u32,
     height: u32,
     pool: Pool,
-    output_buffers: Vec<Vec<u8>>,
     write_eol: bool,
+    settings: RenderSettings,
+    frame_diffing: bool,
+    /* cells of the last frame written, empty when the next one must be fully redrawn */
+    previous: Vec<Cell>,
+    /* frames drawn with a graphics protocol replace the image with this id */
+    image_id: u32,
 }
 
 impl ThreadedEngine {
-    pub fn render(
-        &mut self,
-        coordinate_to_rgba: &(dyn Fn(u32, u32) -> (u8, u8, u8, u8) + Sync + Send),
-    ) {
-        render_thread_pool(
+    /* the cursor is expected to be back on the line where the previous frame started,
+     * frames start from the first column and only the cells which changed are written */
+    pub fn render<P: PixelSource + Sync + ?Sized>(&mut self, source: &P) -> Result<(), Error> {
+        if self.settings.backend != Backend::Cells {
+            let stdout = io::stdout();
+            let mut handle = stdout.lock();
+            return Renderer::new(self.width, self.height)
+                .write_eol(self.write_eol)
+                .restart_start_of_line(true)
+                .settings(self.settings.clone())
+                .image_id(Some(self.image_id))
+                .sink(&mut handle)
+                .render(source);
+        }
+        let cells = render_cells_thread_pool(
             self.width,
+            0,
             self.height,
-            coordinate_to_rgba,
-            self.write_eol,
+            source,
             &mut self.pool,
-            &mut self.output_buffers,
-        )
+            &self.settings,
+        );
+        let columns = self.width / 8;
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        /* without cursor movement, frames can only be written one after the other */
+        let diffing = self.frame_diffing && !self.settings.plain;
+        if diffing && !cells.is_empty() && self.previous.len() == cells.len() {
+            write_cells_diff(
+                &cells,
+                &self.previous,
+                columns,
+                self.write_eol,
+                &mut handle,
+                &self.settings,
+            )?;
+        } else {
+            /* starts from the first column, as the cells written when diffing are */
+            write_cells(
+                &cells,
+                columns,
+                self.write_eol,
+                true,
+                &mut handle,
+                None,
+                &self.settings,
+            )?;
+        }
+        handle.flush()?;
+        self.previous = cells;
+        Ok(())
+    }
+    pub fn set_frame_diffing(&mut self, frame_diffing: bool) {
+        self.frame_diffing = frame_diffing;
+        self.previous.clear();
+    }
+    /* to call when something else drew over the frame, e.g. after the terminal was cleared */
+    pub fn force_redraw(&mut self) {
+        self.previous.clear();
     }
     pub fn new(width: u32, height: u32, write_eol: bool) -> ThreadedEngine {
+        ThreadedEngine::new_with_settings(width, height, write_eol, RenderSettings::detect())
+    }
+    pub fn new_with_settings(
+        width: u32,
+        height: u32,
+        write_eol: bool,
+        settings: RenderSettings,
+    ) -> ThreadedEngine {
         let num_threads = num_cpus::get() * 2;
         let pool = scoped_threadpool::Pool::new(num_threads as u32);
-        let output_buffers = vec![vec![0; (width * height) as usize]; num_threads as usize];
         ThreadedEngine {
             width: width,
             height: height,
             pool: pool,
-            output_buffers: output_buffers,
             write_eol: write_eol,
+            settings: settings,
+            frame_diffing: true,
+            previous: Vec::new(),
+            image_id: kitty::new_image_id(),
         }
     }
 }
 
-fn render_image_result(img: DynamicImage, width: u32, pos: Option<(u32, u32)>) {
-    let height = img.height() * width / img.width();
-    let subimg = img.resize(width, height, FilterType::Nearest);
-    let raw: Vec<u8> = subimg.to_rgba8().into_raw();
-    let raw_slice = raw.as_slice();
-    let width = subimg.width();
-    let height = subimg.height();
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fit {
+    /* scaled to this width in pixels, keeping the aspect ratio */
+    Width(u32),
+    /* scaled to fill the terminal width or height, keeping the aspect ratio,
+     * an error when the terminal size is unknown */
+    Terminal,
+    /* one pixel of the image per pixel of the cells */
+    Original,
+}
 
-    render(
-        width,
-        height,
-        &|x, y| {
-            let start = ((y * width + x) * 4) as usize;
-            (
-                raw_slice[start],
-                raw_slice[start + 1],
-                raw_slice[start + 2],
-                raw_slice[start + 3],
-            )
+pub fn render_image_view<I: GenericImageView<Pixel = Rgba<u8>>>(
+    img: &I,
+    fit: Fit,
+    pos: Option<(u32, u32)>,
+) -> Result<(), Error> {
+    render_image_view_with_settings(img, fit, pos, &RenderSettings::detect())
+}
+
+/* renders a DynamicImage, an RgbaImage or any view of the image crate */
+pub fn render_image_view_with_settings<I: GenericImageView<Pixel = Rgba<u8>>>(
+    img: &I,
+    fit: Fit,
+    pos: Option<(u32, u32)>,
+    settings: &RenderSettings,
+) -> Result<(), Error> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    let width = match fit {
+        Fit::Width(width) => width,
+        Fit::Terminal => match term_size::dimensions() {
+            Some((tw, th)) => {
+                let terminal_width = (tw * 8) as u32;
+                let terminal_heigth = (th * 8 * 2) as u32;
+                (width * terminal_heigth / height).min(terminal_width)
+            }
+            None => return Err(Error::TerminalSize),
         },
-        pos,
-    );
+        Fit::Original => {
+            return Renderer::new(width, height)
+                .pos(pos)
+                .settings(settings.clone())
+                .render(&ImageView(img));
+        }
+    };
+    let height = height * width / img.width();
+    let raw = imageops::resize(img, width, height, FilterType::Nearest).into_raw();
+
+    Renderer::new(width, height)
+        .pos(pos)
+        .settings(settings.clone())
+        .render(&Pixels::rgba8(&raw, width))
+}
+
+pub fn render_image(path: &str, width: u32, pos: Option<(u32, u32)>) -> Result<(), Error> {
+    render_image_with_settings(path, width, pos, &RenderSettings::detect())
+}
+
+pub fn render_image_with_settings(
+    path: &str,
+    width: u32,
+    pos: Option<(u32, u32)>,
+    settings: &RenderSettings,
+) -> Result<(), Error> {
+    render_image_view_with_settings(&image::open(path)?, Fit::Width(width), pos, settings)
+}
+
+/* uses a graphics protocol when the terminal supports one */
+pub fn render_image_fitting_terminal(path: &str) -> Result<(), Error> {
+    let settings = RenderSettings {
+        backend: Backend::detect(),
+        ..RenderSettings::detect()
+    };
+    render_image_fitting_terminal_with_settings(path, &settings)
 }
 
-pub fn render_image(path: &str, width: u32, pos: Option<(u32, u32)>) {
-    render_image_result(image::open(path).unwrap(), width, pos);
+pub fn render_image_fitting_terminal_with_settings(
+    path: &str,
+    settings: &RenderSettings,
+) -> Result<(), Error> {
+    /* the image is only opened when it can be rendered */
+    if term_size::dimensions().is_none() {
+        return Err(Error::TerminalSize);
+    }
+    render_image_view_with_settings(&image::open(path)?, Fit::Terminal, None, settings)
 }
 
-pub fn render_image_fitting_terminal(path: &str) {
-    if let Some((tw, th)) = term_size::dimensions() {
-        let img = image::open(path).unwrap();
-        let terminal_width = (tw * 8) as u32;
-        let terminal_heigth = (th * 8 * 2) as u32;
-        let width = img.width() * terminal_heigth / img.height();
-        let width = if width > terminal_width {
-            terminal_width
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* 4 x 2 cells of shapes: halves, quarters, a diagonal and a plain color */
+    fn fixture(x: u32, y: u32) -> (u8, u8, u8, u8) {
+        let (column, dx, dy) = (x / 8 + y / 16 * 4, x % 8, y % 16);
+        let on = match column {
+            0 => dy < 8,
+            1 => dx < 4,
+            2 => dx < 4 && dy >= 8,
+            3 => dx * 2 >= dy,
+            4 => dx >= 6,
+            5 => false,
+            6 => dy >= 12,
+            _ => (dx < 4) != (dy < 8),
+        };
+        if on {
+            (250, 200 - column as u8 * 20, 40, 255)
         } else {
-            width
+            (20, 30 + column as u8 * 20, 160, 255)
+        }
+    }
+
+    /* (character, foreground, background) of each cell, row by row */
+    const EXPECTED: [(char, u32, u32); 8] = [
+        ('▀', 0xfac828, 0x141ea0),
+        ('▌', 0xfab428, 0x1432a0),
+        ('▖', 0x1446a0, 0x1446a0),
+        ('▜', 0xfa8c28, 0xfa8c28),
+        ('▕', 0xfa7828, 0x146ea0),
+        ('█', 0x1482a0, 0x1482a0),
+        ('▂', 0xfa5028, 0x1496a0),
+        ('█', 0x14aaa0, 0x14aaa0),
+    ];
+
+    fn rgb(color: (u8, u8, u8, u8)) -> u32 {
+        (color.0 as u32) << 16 | (color.1 as u32) << 8 | color.2 as u32
+    }
+
+    #[test]
+    fn render_cells_matches_expected_grid() {
+        let cells = render_cells(32, 32, &fixture, &RenderSettings::default());
+        let grid: Vec<(char, u32, u32)> = cells
+            .iter()
+            .map(|cell| (cell.character, rgb(cell.fg), rgb(cell.bg)))
+            .collect();
+        assert_eq!(grid, EXPECTED);
+        assert!(cells
+            .iter()
+            .all(|cell| cell.fg.3 == 255 && cell.bg.3 == 255));
+    }
+
+    /* the two left columns of the bottom half are as far from ▎ as from ▏ and ▖ */
+    #[test]
+    fn first_of_equally_close_glyphs_wins() {
+        let mask = GlyphSet::mask_from_rows([0, 0, 0, 0, 0xc0, 0xc0, 0xc0, 0xc0]);
+        let settings = RenderSettings::default();
+        let distances: Vec<u32> = settings
+            .glyph_set
+            .glyphs
+            .iter()
+            .map(|(glyph, _)| (glyph ^ mask).count_ones())
+            .collect();
+        let closest = *distances.iter().min().unwrap();
+        let tied: Vec<char> = settings
+            .glyph_set
+            .glyphs
+            .iter()
+            .zip(&distances)
+            .filter(|(_, distance)| **distance == closest)
+            .map(|((_, (_, character)), _)| *character)
+            .collect();
+        assert_eq!(tied, ['▎', '▏', '▖']);
+
+        let cell = |x: u32, y: u32| {
+            if mask_contains(mask, (y / 2 * 8 + x) as usize) {
+                (255, 255, 255, 255)
+            } else {
+                (0, 0, 0, 255)
+            }
+        };
+        let cells = render_cells(8, 16, &cell, &settings);
+        assert_eq!(cells[0].character, '▎');
+    }
+
+    /* every glyph fits a cell of a single color as well as the full block does */
+    #[test]
+    fn uniform_cells_are_full_blocks() {
+        for matcher in [Matcher::RgbError, Matcher::OklabError] {
+            for v in [10u8, 77, 128, 200, 255] {
+                let settings = RenderSettings {
+                    matcher,
+                    ..RenderSettings::default()
+                };
+                let color = (v, v / 2, 255 - v, 255);
+                let cells = render_cells(8, 16, &|_, _| color, &settings);
+                assert_eq!(cells[0].character, '█', "{:?} {:?}", matcher, color);
+            }
+        }
+    }
+
+    /* the screen a terminal would show after the output of write_cells */
+    struct Screen {
+        rows: Vec<Vec<(char, Paint, Paint, Attributes)>>,
+        row: usize,
+        column: usize,
+        fg: Paint,
+        bg: Paint,
+        attributes: Attributes,
+    }
+
+    impl Screen {
+        fn new() -> Screen {
+            Screen {
+                rows: vec![Vec::new()],
+                row: 0,
+                column: 0,
+                fg: Paint::Default,
+                bg: Paint::Default,
+                attributes: Attributes::default(),
+            }
+        }
+
+        /* the rows without the foreground of spaces, which cannot be seen */
+        fn visible(&self) -> Vec<Vec<(char, Paint, Paint, Attributes)>> {
+            let mut rows = self.rows.clone();
+            for cell in rows.iter_mut().flatten() {
+                if cell.0 == ' ' && !cell.3.underline {
+                    cell.1 = Paint::Default;
+                }
+            }
+            rows
+        }
+
+        fn down(&mut self, n: usize) {
+            self.row += n;
+            while self.rows.len() <= self.row {
+                self.rows.push(Vec::new());
+            }
+        }
+
+        fn put(&mut self, character: char) {
+            let cell = (character, self.fg, self.bg, self.attributes);
+            let row = &mut self.rows[self.row];
+            if row.len() <= self.column {
+                row.resize(
+                    self.column + 1,
+                    ('?', Paint::Default, Paint::Default, Attributes::default()),
+                );
+            }
+            row[self.column] = cell;
+        }
+
+        /* what the terminal does with the output, from where the cursor is */
+        fn apply(&mut self, output: &str) {
+            let mut characters = output.chars();
+            while let Some(character) = characters.next() {
+                match character {
+                    '\x1b' => {
+                        assert_eq!(characters.next(), Some('['));
+                        let mut params = String::new();
+                        let command = loop {
+                            match characters.next().unwrap() {
+                                c if c.is_ascii_digit() || c == ';' => params.push(c),
+                                c => break c,
+                            }
+                        };
+                        let params: Vec<u32> = params
+                            .split(';')
+                            .filter(|param| !param.is_empty())
+                            .map(|param| param.parse().unwrap())
+                            .collect();
+                        let n = params.first().copied().unwrap_or(1) as usize;
+                        match command {
+                            'm' => self.sgr(&params),
+                            'G' => self.column = n.max(1) - 1,
+                            'B' => self.down(n),
+                            'C' => self.column += n,
+                            /* erasing does not move the cursor */
+                            'X' => {
+                                let column = self.column;
+                                for _ in 0..n {
+                                    self.put(' ');
+                                    self.column += 1;
+                                }
+                                self.column = column;
+                            }
+                            _ => panic!("unexpected escape sequence {}", command),
+                        }
+                    }
+                    '\n' => {
+                        self.down(1);
+                        self.column = 0;
+                    }
+                    _ => {
+                        self.put(character);
+                        self.column += 1;
+                    }
+                }
+            }
+        }
+
+        fn sgr(&mut self, params: &[u32]) {
+            let mut params = params.iter().copied();
+            while let Some(param) = params.next() {
+                let mut color = |base: u32| match param - base {
+                    8 => match params.next() {
+                        Some(5) => Paint::Indexed(params.next().unwrap() as u8),
+                        Some(2) => {
+                            let mut channel = || params.next().unwrap() as u8;
+                            Paint::Rgb(channel(), channel(), channel())
+                        }
+                        _ => panic!("unexpected parameters after {}", param),
+                    },
+                    9 => Paint::Default,
+                    i if base >= 90 => Paint::Indexed(i as u8 + 8),
+                    i => Paint::Indexed(i as u8),
+                };
+                match param {
+                    0 => {
+                        self.fg = Paint::Default;
+                        self.bg = Paint::Default;
+                        self.attributes = Attributes::default();
+                    }
+                    1 | 22 => self.attributes.bold = param == 1,
+                    3 | 23 => self.attributes.italic = param == 3,
+                    4 | 24 => self.attributes.underline = param == 4,
+                    7 | 27 => self.attributes.reverse = param == 7,
+                    30..=37 | 39 => self.fg = color(30),
+                    38 => self.fg = color(30),
+                    40..=47 | 49 => self.bg = color(40),
+                    48 => self.bg = color(40),
+                    90..=97 => self.fg = color(90),
+                    100..=107 => self.bg = color(100),
+                    _ => panic!("unexpected parameter {}", param),
+                }
+            }
+        }
+    }
+
+    fn decode(output: &str) -> Screen {
+        let mut screen = Screen::new();
+        screen.apply(output);
+        screen
+    }
+
+    const RED: Color = (205, 0, 0, 255);
+    const GREEN: Color = (0, 205, 0, 255);
+    const BLUE: Color = (0, 0, 238, 255);
+    const YELLOW: Color = (205, 205, 0, 255);
+    const WHITE: Color = (255, 255, 255, 255);
+    const DEFAULT: Color = (0, 0, 0, 0);
+
+    fn cell(character: char, fg: Color, bg: Color) -> Cell {
+        Cell {
+            character,
+            fg,
+            bg,
+            attributes: Attributes::default(),
+        }
+    }
+
+    /* a row with a foreground change, a background change and 7 blanks,
+     * then a row starting with 9 blanks and cells with attributes */
+    fn grid() -> Vec<Cell> {
+        let mut cells = vec![cell('▀', RED, BLUE); 3];
+        cells.push(cell('▀', GREEN, BLUE));
+        cells.push(cell('▀', GREEN, YELLOW));
+        cells.extend(vec![cell(' ', WHITE, DEFAULT); 7]);
+        cells.extend(vec![cell(' ', DEFAULT, DEFAULT); 9]);
+        let mut bold = cell('▄', WHITE, BLUE);
+        bold.attributes.bold = true;
+        cells.push(bold);
+        cells.push(cell(' ', GREEN, RED));
+        let mut underline = cell('▌', RED, DEFAULT);
+        underline.attributes.underline = true;
+        cells.push(underline);
+        cells
+    }
+
+    fn write(cells: &[Cell], mode: ColorMode) -> String {
+        let settings = RenderSettings {
+            color_mode: mode,
+            ..RenderSettings::default()
+        };
+        let mut out = Vec::new();
+        write_cells(cells, 12, true, false, &mut out, None, &settings).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn write_cells_decodes_to_the_grid() {
+        let cells = grid();
+        for mode in [
+            ColorMode::TrueColor,
+            ColorMode::Xterm256,
+            ColorMode::Ansi16,
+            ColorMode::None,
+        ] {
+            let screen = decode(&write(&cells, mode));
+            assert_eq!(screen.rows.len(), 3, "{:?}", mode);
+            assert!(screen.rows[2].is_empty(), "{:?}", mode);
+            for (y, row) in cells.chunks(12).enumerate() {
+                assert_eq!(screen.rows[y].len(), 12, "{:?} row {}", mode, y);
+                for (x, cell) in row.iter().enumerate() {
+                    let (character, fg, bg, attributes) = screen.rows[y][x];
+                    let at = format!("{:?} at {}, {}", mode, x, y);
+                    assert_eq!(character, cell.character, "{}", at);
+                    assert_eq!(bg, paint(cell.bg, mode), "{}", at);
+                    /* the foreground of a space is not visible */
+                    if cell.character != ' ' {
+                        assert_eq!(fg, paint(cell.fg, mode), "{}", at);
+                    }
+                    if mode != ColorMode::None {
+                        assert_eq!(attributes, cell.attributes, "{}", at);
+                    }
+                }
+            }
+            /* the colors and attributes are reset at the end of each line */
+            assert_eq!((screen.fg, screen.bg), (Paint::Default, Paint::Default));
+            assert_eq!(screen.attributes, Attributes::default());
+        }
+    }
+
+    #[test]
+    fn write_cells_only_writes_changes() {
+        let out = write(&grid(), ColorMode::TrueColor);
+        /* same colors, then only the foreground, then only the background */
+        assert!(out.starts_with(
+            "\x1b[38;2;205;0;0;48;2;0;0;238m▀▀▀\x1b[38;2;0;205;0m▀\x1b[48;2;205;205;0m▀"
+        ));
+        /* 7 blanks are written as spaces, 9 are erased and skipped */
+        assert!(out.contains("▀\x1b[49m       \x1b[39m\n"));
+        assert!(out.contains("\n\x1b[9X\x1b[9C"));
+
+        let out = write(&grid(), ColorMode::Ansi16);
+        assert!(out.starts_with("\x1b[31;44m▀▀▀\x1b[32m▀\x1b[43m▀"));
+
+        let out = write(&grid(), ColorMode::None);
+        assert_eq!(out, "▀▀▀▀▀       \n         ▄ ▌\n");
+    }
+
+    #[test]
+    fn renderer_writes_cells_with_its_settings() {
+        let mut out = Vec::new();
+        Renderer::new(12 * 8, 3 * 16)
+            .settings(RenderSettings::default())
+            .restart_start_of_line(false)
+            .sink(&mut out)
+            .write_cells(&grid())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            write(&grid(), ColorMode::TrueColor)
+        );
+    }
+
+    #[test]
+    fn thread_pool_renders_as_a_single_thread() {
+        let mut pool = Pool::new(3);
+        for band in [(0, 32), (16, 32), (5, 27)] {
+            let mut single = Vec::new();
+            Renderer::new(32, 32)
+                .band(band.0, band.1)
+                .settings(RenderSettings::default())
+                .sink(&mut single)
+                .render(&fixture)
+                .unwrap();
+            let mut threaded = Vec::new();
+            Renderer::new(32, 32)
+                .band(band.0, band.1)
+                .settings(RenderSettings::default())
+                .sink(&mut threaded)
+                .render_thread_pool(&fixture, &mut pool)
+                .unwrap();
+            assert!(!single.is_empty());
+            assert_eq!(threaded, single, "{:?}", band);
+        }
+    }
+
+    fn sixel(cell_size: (u32, u32)) -> String {
+        let mut out = Vec::new();
+        Renderer::new(32, 32)
+            .settings(RenderSettings {
+                backend: Backend::Sixel,
+                ..RenderSettings::default()
+            })
+            .restart_start_of_line(false)
+            .write_eol(false)
+            .cell_size(Some(cell_size))
+            .sink(&mut out)
+            .render(&fixture)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /* 4 x 2 cells cover 40 x 40 pixels with cells of 10 x 20 pixels */
+    #[test]
+    fn sixel_images_cover_the_cells() {
+        let out = sixel((10, 20));
+        assert!(out.starts_with("\x1bP0;1q\"1;1;40;40#"), "{:?}", out);
+        assert!(out.ends_with("\x1b\\"));
+        /* 40 rows are 7 bands of 6 rows, separated by 6 new lines */
+        assert_eq!(out.matches('-').count(), 6);
+        assert!(sixel((8, 16)).starts_with("\x1bP0;1q\"1;1;32;32#"));
+    }
+
+    /* the test vectors of RFC 4648 */
+    #[test]
+    fn base64_test_vectors() {
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(data.as_bytes()), encoded);
+        }
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+
+    /* less than a cell wide or high is nothing to draw, whatever the protocol */
+    #[test]
+    fn graphics_smaller_than_a_cell_draw_nothing() {
+        for backend in [Backend::Kitty, Backend::Iterm] {
+            for (width, band) in [(4, (0, 32)), (32, (0, 12))] {
+                let mut out = Vec::new();
+                Renderer::new(width, 32)
+                    .band(band.0, band.1)
+                    .settings(RenderSettings {
+                        backend,
+                        ..RenderSettings::default()
+                    })
+                    .restart_start_of_line(false)
+                    .write_eol(false)
+                    .sink(&mut out)
+                    .render(&fixture)
+                    .unwrap();
+                assert!(out.is_empty(), "{:?} {:?}", backend, out);
+            }
+        }
+    }
+
+    fn frames(mode: ColorMode, write_eol: bool, previous: &[Cell], cells: &[Cell]) -> [String; 2] {
+        let settings = RenderSettings {
+            color_mode: mode,
+            ..RenderSettings::default()
         };
+        let mut full = Vec::new();
+        write_cells(cells, 12, write_eol, true, &mut full, None, &settings).unwrap();
+        let mut diff = Vec::new();
+        write_cells_diff(cells, previous, 12, write_eol, &mut diff, &settings).unwrap();
+        [
+            String::from_utf8(full).unwrap(),
+            String::from_utf8(diff).unwrap(),
+        ]
+    }
 
-        render_image_result(img, width, None);
+    /* the previous frame with the cursor moved back to its start, then the diff,
+     * leaves the screen and the cursor as writing the new frame does */
+    #[test]
+    fn write_cells_diff_matches_a_full_frame() {
+        let mut previous = grid();
+        previous.extend(vec![cell('▄', RED, GREEN); 12]);
+        let mut changes = Vec::new();
+        /* changes apart and next to each other on the first row, none on the second */
+        let mut cells = previous.clone();
+        cells[1] = cell('▀', YELLOW, BLUE);
+        cells[4] = cell('▄', RED, GREEN);
+        cells[5] = cell(' ', DEFAULT, DEFAULT);
+        cells[31] = cell('▌', WHITE, RED);
+        changes.push(cells);
+        /* only the first row, so the cursor is moved down to the end of the frame */
+        let mut cells = previous.clone();
+        cells[11] = cell('▐', GREEN, DEFAULT);
+        changes.push(cells);
+        /* only the last cell */
+        let mut cells = previous.clone();
+        cells[35] = cell('█', BLUE, BLUE);
+        changes.push(cells);
+        changes.push(previous.clone());
+
+        for mode in [
+            ColorMode::TrueColor,
+            ColorMode::Xterm256,
+            ColorMode::Ansi16,
+            ColorMode::None,
+        ] {
+            for cells in &changes {
+                let [_, diff] = frames(mode, true, &previous, cells);
+                let [full, _] = frames(mode, true, &previous, &previous);
+                let mut screen = decode(&full);
+                screen.row = 0;
+                screen.column = 0;
+                screen.apply(&diff);
+                let [full, _] = frames(mode, true, &previous, cells);
+                let expected = decode(&full);
+                assert_eq!(
+                    screen.visible(),
+                    expected.visible(),
+                    "{:?} {:?}",
+                    mode,
+                    diff
+                );
+                assert_eq!((screen.row, screen.column), (expected.row, expected.column));
+
+                /* without new lines, a frame is a single row and the cursor stays after it */
+                let [_, diff] = frames(mode, false, &previous[..12], &cells[..12]);
+                let [full, _] = frames(mode, false, &previous[..12], &previous[..12]);
+                let mut screen = decode(&full);
+                screen.apply(&diff);
+                let [full, _] = frames(mode, false, &previous[..12], &cells[..12]);
+                let expected = decode(&full);
+                assert_eq!(
+                    screen.visible(),
+                    expected.visible(),
+                    "{:?} {:?}",
+                    mode,
+                    diff
+                );
+                assert_eq!((screen.row, screen.column), (0, 12));
+                assert_eq!((expected.row, expected.column), (0, 12));
+            }
+        }
     }
 }
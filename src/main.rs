@@ -1,23 +1,198 @@
 extern crate clap;
-use blockish::{render_image, render_image_fitting_terminal};
+use blockish::{
+    render_image_fitting_terminal_with_settings, render_image_with_settings, Backend, ColorMode,
+    ColorSelection, Dither, Error, GlyphSet, Luminance, Matcher, Mode, RenderSettings, Sampling,
+    Threshold,
+};
 use clap::{arg, command, value_parser};
+use std::io;
+
+/* the width of html output without --width, in columns */
+const HTML_COLUMNS: u32 = 80;
 
 fn main() {
     let matches = command!() // requires `cargo` feature
         .arg(arg!([input] "the input file to use"))
         .arg(
             arg!(
-                -w --width <WIDTH> "width of the image"
+                -w --width <WIDTH> "width of the image in columns, the terminal width by default or 80 for html"
             )
             // We don't have syntax yet for optional options, so manually calling `required`
             .required(false)
             .value_parser(value_parser!(u32)),
         )
+        .arg(
+            arg!(
+                -b --backend <BACKEND> "draw the image with characters or with a graphics protocol"
+            )
+            .required(false)
+            .value_parser(["auto", "cells", "sixel", "kitty", "iterm"])
+            .default_value("auto"),
+        )
+        .arg(
+            arg!(
+                -f --format <FORMAT> "write escape codes for the terminal, or html"
+            )
+            .required(false)
+            .value_parser(["terminal", "html", "html-page"])
+            .default_value("terminal"),
+        )
+        .arg(
+            arg!(
+                -m --mode <MODE> "characters used to draw the image"
+            )
+            .required(false)
+            .value_parser(["blocks", "braille"])
+            .default_value("blocks"),
+        )
+        .arg(
+            arg!(
+                -g --glyphs <GLYPHS> "characters the blocks mode can choose from"
+            )
+            .required(false)
+            .value_parser(["auto", "box", "blocks", "quadrants", "ascii"])
+            .default_value("auto"),
+        )
+        .arg(
+            arg!(
+                --exclude <CHARACTERS> "characters the blocks mode must not use"
+            )
+            .required(false),
+        )
+        .arg(
+            arg!(
+                --matcher <MATCHER> "how the blocks mode picks a character for each cell"
+            )
+            .required(false)
+            .value_parser(["hamming", "rgb", "oklab"])
+            .default_value("hamming"),
+        )
+        .arg(arg!(--full "sample every pixel instead of one row out of two"))
+        .arg(arg!(--kmeans "pick each cell colors by splitting its pixels in two clusters"))
+        .arg(arg!(--linear "use the Rec.709 luma of linear colors and average in linear light"))
+        .arg(
+            arg!(
+                --threshold <THRESHOLD> "grey level splitting each cell in two colors"
+            )
+            .required(false)
+            .value_parser(["mean", "median", "otsu", "midpoint"])
+            .default_value("mean"),
+        )
+        .arg(
+            arg!(
+                -c --colors <COLORS> "colors the terminal can display"
+            )
+            .required(false)
+            .value_parser(["auto", "truecolor", "256", "16", "none"])
+            .default_value("auto"),
+        )
+        .arg(
+            arg!(
+                -d --dither <DITHER> "dithering of the colors in 256 and 16 color modes"
+            )
+            .required(false)
+            .value_parser(["none", "floyd-steinberg", "atkinson", "bayer"])
+            .default_value("none"),
+        )
+        .arg(arg!(--sextants "also use sextant characters (2x3 blocks)"))
+        .arg(arg!(--octants "also use octant characters (2x4 blocks)"))
         .get_matches();
 
     let path = matches.get_one::<String>("input").expect("no input given");
-    match matches.get_one::<u32>("width") {
-        Some(width) => render_image(path, width * 8, None),
-        None => render_image_fitting_terminal(path),
+    let format = matches.get_one::<String>("format").map(|s| s.as_str());
+    let html = format == Some("html") || format == Some("html-page");
+    /* html is shown by browsers, which display all the characters and colors */
+    let detected = if html {
+        RenderSettings::default()
+    } else {
+        RenderSettings::detect()
+    };
+    let mut glyph_set = match matches.get_one::<String>("glyphs").map(|s| s.as_str()) {
+        Some("box") => GlyphSet::blocks_and_box(),
+        Some("blocks") => GlyphSet::blocks(),
+        Some("quadrants") => GlyphSet::quadrants(),
+        Some("ascii") => GlyphSet::ascii(),
+        _ => detected.glyph_set,
+    };
+    if matches.get_flag("sextants") {
+        glyph_set = glyph_set.with(&GlyphSet::sextants());
+    }
+    if matches.get_flag("octants") {
+        glyph_set = glyph_set.with(&GlyphSet::octants());
+    }
+    if let Some(characters) = matches.get_one::<String>("exclude") {
+        glyph_set = glyph_set.without(characters);
+    }
+    let settings = RenderSettings {
+        backend: match matches.get_one::<String>("backend").map(|s| s.as_str()) {
+            _ if html => Backend::Html {
+                standalone: format == Some("html-page"),
+            },
+            Some("sixel") => Backend::Sixel,
+            Some("kitty") => Backend::Kitty,
+            Some("iterm") => Backend::Iterm,
+            Some("cells") => Backend::Cells,
+            _ => Backend::detect(),
+        },
+        mode: match matches.get_one::<String>("mode").map(|s| s.as_str()) {
+            Some("braille") => Mode::Braille,
+            _ => Mode::Blocks,
+        },
+        glyph_set,
+        matcher: match matches.get_one::<String>("matcher").map(|s| s.as_str()) {
+            Some("rgb") => Matcher::RgbError,
+            Some("oklab") => Matcher::OklabError,
+            _ => Matcher::Hamming,
+        },
+        sampling: if matches.get_flag("full") {
+            Sampling::Full
+        } else {
+            Sampling::Fast
+        },
+        color_selection: if matches.get_flag("kmeans") {
+            ColorSelection::KMeans
+        } else {
+            ColorSelection::Quartiles
+        },
+        luminance: if matches.get_flag("linear") {
+            Luminance::Rec709
+        } else {
+            Luminance::Sum
+        },
+        threshold: match matches.get_one::<String>("threshold").map(|s| s.as_str()) {
+            Some("median") => Threshold::Median,
+            Some("otsu") => Threshold::Otsu,
+            Some("midpoint") => Threshold::Midpoint,
+            _ => Threshold::Mean,
+        },
+        color_mode: match matches.get_one::<String>("colors").map(|s| s.as_str()) {
+            Some("truecolor") => ColorMode::TrueColor,
+            Some("256") => ColorMode::Xterm256,
+            Some("16") => ColorMode::Ansi16,
+            Some("none") => ColorMode::None,
+            _ => detected.color_mode,
+        },
+        dither: match matches.get_one::<String>("dither").map(|s| s.as_str()) {
+            Some("floyd-steinberg") => Dither::FloydSteinberg,
+            Some("atkinson") => Dither::Atkinson,
+            Some("bayer") => Dither::Bayer,
+            _ => Dither::None,
+        },
+        plain: detected.plain,
+    };
+    let result = match matches.get_one::<u32>("width") {
+        Some(width) => render_image_with_settings(path, width * 8, None, &settings),
+        /* a page does not depend on the terminal it was written from */
+        None if html => render_image_with_settings(path, HTML_COLUMNS * 8, None, &settings),
+        None => render_image_fitting_terminal_with_settings(path, &settings),
     };
+    match result {
+        /* like other commands, stop quietly when the reader went away, as with head */
+        Err(Error::Io(error)) if error.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
+        }
+        Ok(()) => {}
+    }
 }
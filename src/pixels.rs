@@ -0,0 +1,256 @@
+use crate::Error;
+use image::codecs::png::PngEncoder;
+use image::{ColorType, GenericImageView, ImageEncoder, Rgba};
+use std::io;
+
+/* where the renderer reads the (r, g, b, a) color of the pixel at (x, y) from,
+ * the renderer is generic over it so that reading a buffer can be inlined */
+pub trait PixelSource {
+    fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8, u8);
+}
+
+impl<F: Fn(u32, u32) -> (u8, u8, u8, u8) + ?Sized> PixelSource for F {
+    #[inline(always)]
+    fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8, u8) {
+        self(x, y)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    Rgba8,
+    Bgra8,
+    Rgb8,
+    Luma8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => 4,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Luma8 => 1,
+        }
+    }
+}
+
+/* a buffer of bytes, rows one after the other, each starting stride bytes after
+ * the previous one, which is width times the size of a pixel unless rows are padded */
+#[derive(Clone, Copy, Debug)]
+pub struct Pixels<'a> {
+    data: &'a [u8],
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    stride: usize,
+    bottom_up: bool,
+}
+
+impl<'a> Pixels<'a> {
+    pub fn new(data: &'a [u8], width: u32, format: PixelFormat) -> Pixels<'a> {
+        Pixels {
+            data,
+            format,
+            width,
+            height: 0,
+            stride: 0,
+            bottom_up: false,
+        }
+        .stride(width as usize * format.bytes_per_pixel())
+    }
+
+    pub fn rgba8(data: &'a [u8], width: u32) -> Pixels<'a> {
+        Pixels::new(data, width, PixelFormat::Rgba8)
+    }
+
+    pub fn bgra8(data: &'a [u8], width: u32) -> Pixels<'a> {
+        Pixels::new(data, width, PixelFormat::Bgra8)
+    }
+
+    pub fn rgb8(data: &'a [u8], width: u32) -> Pixels<'a> {
+        Pixels::new(data, width, PixelFormat::Rgb8)
+    }
+
+    pub fn luma8(data: &'a [u8], width: u32) -> Pixels<'a> {
+        Pixels::new(data, width, PixelFormat::Luma8)
+    }
+
+    /* the last row does not need to be padded */
+    pub fn stride(mut self, stride: usize) -> Pixels<'a> {
+        let row = self.width as usize * self.format.bytes_per_pixel();
+        self.stride = stride;
+        self.height = if stride == 0 || self.data.len() < row {
+            0
+        } else {
+            ((self.data.len() - row) / stride + 1) as u32
+        };
+        self
+    }
+
+    /* the first row of the buffer is the bottom of the image, as in OpenGL */
+    pub fn bottom_up(mut self, bottom_up: bool) -> Pixels<'a> {
+        self.bottom_up = bottom_up;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl<'a> PixelSource for Pixels<'a> {
+    #[inline(always)]
+    fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8, u8) {
+        let row = if self.bottom_up {
+            self.height - 1 - y
+        } else {
+            y
+        };
+        let start = row as usize * self.stride + x as usize * self.format.bytes_per_pixel();
+        let data = self.data;
+        match self.format {
+            PixelFormat::Rgba8 => (
+                data[start],
+                data[start + 1],
+                data[start + 2],
+                data[start + 3],
+            ),
+            PixelFormat::Bgra8 => (
+                data[start + 2],
+                data[start + 1],
+                data[start],
+                data[start + 3],
+            ),
+            PixelFormat::Rgb8 => (data[start], data[start + 1], data[start + 2], 255),
+            PixelFormat::Luma8 => (data[start], data[start], data[start], 255),
+        }
+    }
+}
+
+/* pixels packed as 0xAARRGGBB in a u32, as rasterizers like euc produce them,
+ * with a stride counted in pixels */
+#[derive(Clone, Copy, Debug)]
+pub struct PackedPixels<'a> {
+    data: &'a [u32],
+    width: u32,
+    height: u32,
+    stride: usize,
+    bottom_up: bool,
+    opaque: bool,
+}
+
+impl<'a> PackedPixels<'a> {
+    pub fn new(data: &'a [u32], width: u32) -> PackedPixels<'a> {
+        PackedPixels {
+            data,
+            width,
+            height: 0,
+            stride: 0,
+            bottom_up: false,
+            opaque: false,
+        }
+        .stride(width as usize)
+    }
+
+    pub fn stride(mut self, stride: usize) -> PackedPixels<'a> {
+        self.stride = stride;
+        self.height = if stride == 0 || self.data.len() < self.width as usize {
+            0
+        } else {
+            ((self.data.len() - self.width as usize) / stride + 1) as u32
+        };
+        self
+    }
+
+    pub fn bottom_up(mut self, bottom_up: bool) -> PackedPixels<'a> {
+        self.bottom_up = bottom_up;
+        self
+    }
+
+    /* ignores the alpha byte, for buffers like 0x00RRGGBB where it is left empty */
+    pub fn opaque(mut self, opaque: bool) -> PackedPixels<'a> {
+        self.opaque = opaque;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+impl<'a> PixelSource for PackedPixels<'a> {
+    #[inline(always)]
+    fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8, u8) {
+        let row = if self.bottom_up {
+            self.height - 1 - y
+        } else {
+            y
+        };
+        let color = self.data[row as usize * self.stride + x as usize];
+        (
+            (color >> 16) as u8,
+            (color >> 8) as u8,
+            color as u8,
+            if self.opaque {
+                255
+            } else {
+                (color >> 24) as u8
+            },
+        )
+    }
+}
+
+/* any image of the image crate, such as a DynamicImage or an RgbaImage */
+#[derive(Clone, Copy, Debug)]
+pub struct ImageView<'a, I>(pub &'a I);
+
+impl<'a, I: GenericImageView<Pixel = Rgba<u8>>> PixelSource for ImageView<'a, I> {
+    #[inline(always)]
+    fn pixel(&self, x: u32, y: u32) -> (u8, u8, u8, u8) {
+        let pixel = self.0.get_pixel(x, y).0;
+        (pixel[0], pixel[1], pixel[2], pixel[3])
+    }
+}
+
+/* the rows from top to bottom as RGBA bytes, for the graphics protocols */
+pub(crate) fn read_rgba<P: PixelSource + ?Sized>(
+    source: &P,
+    width: u32,
+    top: u32,
+    bottom: u32,
+) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity((width * bottom.saturating_sub(top) * 4) as usize);
+    for y in top..bottom {
+        for x in 0..width {
+            let (r, g, b, a) = source.pixel(x, y);
+            rgba.extend_from_slice(&[r, g, b, a]);
+        }
+    }
+    rgba
+}
+
+pub(crate) fn check_rgba(rgba: &[u8], width: u32, height: u32) -> Result<(), Error> {
+    if rgba.len() == (width * height * 4) as usize {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the RGBA buffer does not match the image size",
+        )))
+    }
+}
+
+pub(crate) fn encode_png(rgba: &[u8], width: u32, height: u32) -> Result<Vec<u8>, Error> {
+    check_rgba(rgba, width, height)?;
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(rgba, width, height, ColorType::Rgba8)?;
+    Ok(png)
+}
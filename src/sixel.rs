@@ -0,0 +1,187 @@
+use crate::{Error, PixelSource};
+use std::io::Write;
+
+const MAX_COLORS: usize = 256;
+const TRANSPARENT: u16 = u16::MAX;
+
+/* colors are binned on 5 bits per channel, bin = r << 10 | g << 5 | b */
+fn bin_channel(bin: u16, channel: usize) -> u16 {
+    (bin >> (10 - 5 * channel)) & 31
+}
+
+/* median cut of the histogram of the bins: the box of bins with the widest
+ * channel is split at the median pixel along it, until there are enough boxes,
+ * and each box becomes the average color of its pixels */
+fn median_cut(histogram: &[(u64, [u64; 3])]) -> (Vec<(u8, u8, u8)>, Vec<u8>) {
+    let mut bins: Vec<u16> = (0..histogram.len() as u16)
+        .filter(|bin| histogram[*bin as usize].0 > 0)
+        .collect();
+    let mut boxes = vec![(0, bins.len())];
+    while boxes.len() < MAX_COLORS {
+        let mut widest: Option<(u16, usize, usize)> = None;
+        for (i, &(start, end)) in boxes.iter().enumerate() {
+            if end - start < 2 {
+                continue;
+            }
+            for channel in 0..3 {
+                let values = bins[start..end]
+                    .iter()
+                    .map(|bin| bin_channel(*bin, channel));
+                let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                if widest.map(|w| range > w.0).unwrap_or(true) {
+                    widest = Some((range, i, channel));
+                }
+            }
+        }
+        let (_, i, channel) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+        let (start, end) = boxes[i];
+        bins[start..end].sort_unstable_by_key(|bin| bin_channel(*bin, channel));
+        let total: u64 = bins[start..end]
+            .iter()
+            .map(|bin| histogram[*bin as usize].0)
+            .sum();
+        let mut count = 0;
+        let mut split = end - 1;
+        for (j, bin) in bins[start..end].iter().enumerate() {
+            count += histogram[*bin as usize].0;
+            if count * 2 >= total {
+                split = (start + j + 1).min(end - 1);
+                break;
+            }
+        }
+        boxes[i] = (start, split);
+        boxes.push((split, end));
+    }
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut indices = vec![0; histogram.len()];
+    for (i, &(start, end)) in boxes.iter().enumerate() {
+        let mut count = 0;
+        let mut sum = [0; 3];
+        for bin in &bins[start..end] {
+            let (bin_count, bin_sum) = histogram[*bin as usize];
+            count += bin_count;
+            for k in 0..3 {
+                sum[k] += bin_sum[k];
+            }
+            indices[*bin as usize] = i as u8;
+        }
+        /* only the single box of an image without any opaque pixel is empty */
+        if count == 0 {
+            continue;
+        }
+        palette.push((
+            (sum[0] / count) as u8,
+            (sum[1] / count) as u8,
+            (sum[2] / count) as u8,
+        ));
+    }
+    (palette, indices)
+}
+
+fn push_run(out: &mut String, sixel: u8, count: usize) {
+    let character = (sixel + 63) as char;
+    if count > 3 {
+        out.push_str(format!("!{}{}", count, character).as_str());
+    } else {
+        for _ in 0..count {
+            out.push(character);
+        }
+    }
+}
+
+/* writes the rows from top to bottom as a sixel image with up to 256 colors,
+ * one pixel of the terminal per pixel, pixels with an alpha below 128 are left untouched */
+pub fn write_sixel<P: PixelSource + ?Sized>(
+    width: u32,
+    top: u32,
+    bottom: u32,
+    source: &P,
+    handle: &mut dyn Write,
+) -> Result<(), Error> {
+    let height = bottom.saturating_sub(top);
+    let mut histogram = vec![(0u64, [0u64; 3]); 1 << 15];
+    let mut bins = Vec::with_capacity((width * height) as usize);
+    for y in top..bottom {
+        for x in 0..width {
+            let (r, g, b, a) = source.pixel(x, y);
+            if a < 128 {
+                bins.push(TRANSPARENT);
+            } else {
+                let bin = (r as u16 >> 3) << 10 | (g as u16 >> 3) << 5 | b as u16 >> 3;
+                let entry = &mut histogram[bin as usize];
+                entry.0 += 1;
+                entry.1[0] += r as u64;
+                entry.1[1] += g as u64;
+                entry.1[2] += b as u64;
+                bins.push(bin);
+            }
+        }
+    }
+    let (palette, indices) = median_cut(&histogram);
+
+    /* P2 = 1 keeps the pixels which are not drawn, the raster attributes set square pixels */
+    let mut out = format!("\x1bP0;1q\"1;1;{};{}", width, height);
+    let percent = |c: u8| (c as u32 * 100 + 127) / 255;
+    for (i, color) in palette.iter().enumerate() {
+        out.push_str(
+            format!(
+                "#{};2;{};{};{}",
+                i,
+                percent(color.0),
+                percent(color.1),
+                percent(color.2)
+            )
+            .as_str(),
+        );
+    }
+    let width = width as usize;
+    let height = height as usize;
+    let mut sixels = vec![0u8; width];
+    for band in (0..height).step_by(6) {
+        let rows = (height - band).min(6);
+        let mut used = [false; MAX_COLORS];
+        for bin in &bins[band * width..(band + rows) * width] {
+            if *bin != TRANSPARENT {
+                used[indices[*bin as usize] as usize] = true;
+            }
+        }
+        let mut first = true;
+        for color in (0..palette.len()).filter(|color| used[*color]) {
+            for (x, sixel) in sixels.iter_mut().enumerate() {
+                *sixel = 0;
+                for row in 0..rows {
+                    let bin = bins[(band + row) * width + x];
+                    if bin != TRANSPARENT && indices[bin as usize] as usize == color {
+                        *sixel |= 1 << row;
+                    }
+                }
+            }
+            /* each color is drawn over the same band, going back to its start with $ */
+            if !first {
+                out.push('$');
+            }
+            first = false;
+            out.push_str(format!("#{}", color).as_str());
+            let end = sixels.iter().rposition(|s| *s != 0).map_or(0, |x| x + 1);
+            let mut x = 0;
+            while x < end {
+                let count = sixels[x..end]
+                    .iter()
+                    .take_while(|s| **s == sixels[x])
+                    .count();
+                push_run(&mut out, sixels[x], count);
+                x += count;
+            }
+        }
+        /* the next band starts 6 rows lower, which the last one does not need */
+        if band + 6 < height {
+            out.push('-');
+        }
+    }
+    out.push_str("\x1b\\");
+    write!(handle, "{}", out)?;
+    Ok(())
+}
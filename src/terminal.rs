@@ -0,0 +1,415 @@
+use crate::ColorMode;
+use lazy_static::lazy_static;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /* the terminal understands escape sequences at all */
+    pub ansi: bool,
+    pub color_mode: ColorMode,
+    /* the block elements and box drawing characters can be displayed */
+    pub unicode: bool,
+    /* the sextants from symbols for legacy computing can be displayed */
+    pub legacy_glyphs: bool,
+    pub sixel: bool,
+    pub kitty_graphics: bool,
+    pub iterm_images: bool,
+    /* width and height of a cell in pixels, when the terminal tells them */
+    pub cell_size: Option<(u32, u32)>,
+}
+
+lazy_static! {
+    static ref DETECTED: Capabilities = Capabilities::from_env(&|name| std::env::var(name).ok());
+    static ref QUERIED: Capabilities = DETECTED.query(QUERY_TIMEOUT);
+}
+
+/* long enough for a terminal over ssh, short enough not to be noticed when none answers */
+const QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+/* asks for the graphics support of kitty with a 1x1 image, for the size of a cell
+ * in pixels, then for the device attributes (DA1), which every terminal answers
+ * and which list 4 for sixel */
+const QUERIES: &[u8] = b"\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[16t\x1b[c";
+
+/* the answers to the queries, read until the device attributes came back;
+ * /dev/tty is set to raw mode with reads returning after a tenth of a second,
+ * through stty to do without bindings to termios */
+#[cfg(unix)]
+fn query_terminal(timeout: Duration) -> Option<String> {
+    use std::fs::{File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::process::Command;
+    use std::time::Instant;
+
+    let stty = |args: &[&str]| -> Option<String> {
+        let output = Command::new("stty")
+            .args(args)
+            .stdin(File::open("/dev/tty").ok()?)
+            .output()
+            .ok()?;
+        if output.status.success() {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            None
+        }
+    };
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+    let saved = stty(&["-g"])?;
+    stty(&["raw", "-echo", "min", "0", "time", "1"])?;
+    let mut answer = String::new();
+    if tty.write_all(QUERIES).and_then(|_| tty.flush()).is_ok() {
+        let start = Instant::now();
+        let mut buffer = [0; 256];
+        while start.elapsed() < timeout && device_attributes(&answer).is_none() {
+            match tty.read(&mut buffer) {
+                Ok(n) => answer.push_str(&String::from_utf8_lossy(&buffer[..n])),
+                Err(_) => break,
+            }
+        }
+    }
+    stty(&[&saved]);
+    Some(answer)
+}
+
+#[cfg(not(unix))]
+fn query_terminal(_timeout: Duration) -> Option<String> {
+    None
+}
+
+/* the parameters of the DA1 answer, ESC [ ? 62 ; 4 ; 22 c */
+fn device_attributes(answer: &str) -> Option<Vec<&str>> {
+    let start = answer.find("\x1b[?")? + 3;
+    let end = start + answer[start..].find('c')?;
+    Some(answer[start..end].split(';').collect())
+}
+
+/* the answer to CSI 16 t, ESC [ 6 ; height ; width t */
+fn cell_size(answer: &str) -> Option<(u32, u32)> {
+    let start = answer.find("\x1b[6;")? + 4;
+    let end = start + answer[start..].find('t')?;
+    let mut size = answer[start..end].split(';').map(|n| n.parse::<u32>().ok());
+    let (height, width) = (size.next()??, size.next()??);
+    if width == 0 || height == 0 {
+        None
+    } else {
+        Some((width, height))
+    }
+}
+
+/* index of max_colors in the numbers section of a compiled terminfo entry */
+const TERMINFO_COLORS: usize = 13;
+
+fn terminfo_number(bytes: &[u8], index: usize) -> Option<i32> {
+    let short = |i: usize| -> Option<usize> {
+        let value = i16::from_le_bytes([*bytes.get(i * 2)?, *bytes.get(i * 2 + 1)?]);
+        if value < 0 {
+            None
+        } else {
+            Some(value as usize)
+        }
+    };
+    /* the extended format stores numbers on 32 bits */
+    let number_size = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let names_size = short(1)?;
+    let bools_count = short(2)?;
+    let numbers_count = short(3)?;
+    if index >= numbers_count {
+        return None;
+    }
+    let mut offset = 12 + names_size + bools_count;
+    if offset % 2 == 1 {
+        offset += 1;
+    }
+    let at = offset + index * number_size;
+    let number = if number_size == 2 {
+        i16::from_le_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]) as i32
+    } else {
+        i32::from_le_bytes([
+            *bytes.get(at)?,
+            *bytes.get(at + 1)?,
+            *bytes.get(at + 2)?,
+            *bytes.get(at + 3)?,
+        ])
+    };
+    if number < 0 {
+        None
+    } else {
+        Some(number)
+    }
+}
+
+fn terminfo_colors(var: &dyn Fn(&str) -> Option<String>) -> Option<i32> {
+    let term = var("TERM")?;
+    let first = term.chars().next()?;
+    let mut directories: Vec<PathBuf> = Vec::new();
+    if let Some(directory) = var("TERMINFO") {
+        directories.push(PathBuf::from(directory));
+    }
+    if let Some(home) = var("HOME") {
+        directories.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Some(list) = var("TERMINFO_DIRS") {
+        for directory in list.split(':').filter(|d| !d.is_empty()) {
+            directories.push(PathBuf::from(directory));
+        }
+    }
+    for directory in &[
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+    ] {
+        directories.push(PathBuf::from(directory));
+    }
+    for directory in directories {
+        /* entries are grouped by first letter, or by its hexadecimal code on macOS */
+        for group in &[first.to_string(), format!("{:x}", first as u32)] {
+            if let Ok(bytes) = fs::read(directory.join(group).join(&term)) {
+                return terminfo_number(&bytes, TERMINFO_COLORS);
+            }
+        }
+    }
+    None
+}
+
+impl Capabilities {
+    pub fn detect() -> Capabilities {
+        *DETECTED
+    }
+
+    /* the capabilities from the environment, refined by asking the terminal
+     * which graphics protocols it supports; this is done once and can take
+     * a fraction of a second when the terminal does not answer */
+    pub fn detect_with_queries() -> Capabilities {
+        *QUERIED
+    }
+
+    /* graphics are only used when the output is the terminal itself,
+     * and the answers of the terminal replace the guesses from the environment */
+    pub fn query(&self, timeout: Duration) -> Capabilities {
+        let mut capabilities = *self;
+        if !io::stdout().is_terminal() || !io::stdin().is_terminal() {
+            capabilities.sixel = false;
+            capabilities.kitty_graphics = false;
+            capabilities.iterm_images = false;
+            return capabilities;
+        }
+        if let Some(answer) = query_terminal(timeout) {
+            if let Some(attributes) = device_attributes(&answer) {
+                capabilities.sixel = attributes.contains(&"4");
+                capabilities.kitty_graphics = answer.contains("\x1b_Gi=31;OK");
+                capabilities.cell_size = cell_size(&answer);
+            }
+        }
+        capabilities
+    }
+
+    pub fn from_env(var: &dyn Fn(&str) -> Option<String>) -> Capabilities {
+        let set = |name: &str| var(name).map(|v| !v.is_empty()).unwrap_or(false);
+        let term = var("TERM").unwrap_or_default();
+        let term_program = var("TERM_PROGRAM").unwrap_or_default();
+        let colorterm = var("COLORTERM").unwrap_or_default();
+        let locale = var("LC_ALL")
+            .filter(|v| !v.is_empty())
+            .or_else(|| var("LC_CTYPE").filter(|v| !v.is_empty()))
+            .or_else(|| var("LANG"))
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let kitty = term == "xterm-kitty" || set("KITTY_WINDOW_ID");
+        let wezterm = term_program == "WezTerm" || term == "wezterm";
+        let iterm = term_program == "iTerm.app" || var("LC_TERMINAL").as_deref() == Some("iTerm2");
+        let foot = term.starts_with("foot");
+        let ghostty = term_program == "ghostty" || term == "xterm-ghostty";
+        let windows_terminal = set("WT_SESSION");
+        /* graphics need to be passed through by multiplexers, which is seldom configured */
+        let multiplexed = set("TMUX") || term.starts_with("screen") || term.starts_with("tmux");
+
+        let ansi = term != "dumb" && (!cfg!(windows) || windows_terminal || !term.is_empty());
+        let colors = terminfo_colors(var);
+        let color_mode = if !ansi || set("NO_COLOR") {
+            ColorMode::None
+        } else if colorterm == "truecolor"
+            || colorterm == "24bit"
+            || term.ends_with("-direct")
+            || kitty
+            || wezterm
+            || iterm
+            || foot
+            || ghostty
+            || windows_terminal
+            || term_program == "vscode"
+            || colors.map(|c| c >= 1 << 24).unwrap_or(false)
+        {
+            ColorMode::TrueColor
+        } else if term.contains("256color") || colors.map(|c| c >= 256).unwrap_or(false) {
+            ColorMode::Xterm256
+        } else if term.is_empty() && !set("CI") {
+            ColorMode::None
+        } else {
+            ColorMode::Ansi16
+        };
+        let unicode = ansi
+            && (locale.contains("utf-8")
+                || locale.contains("utf8")
+                || kitty
+                || wezterm
+                || iterm
+                || foot
+                || ghostty
+                || windows_terminal);
+        let legacy_glyphs = unicode && (kitty || wezterm || foot || ghostty);
+
+        Capabilities {
+            ansi,
+            color_mode,
+            unicode,
+            legacy_glyphs,
+            sixel: !multiplexed && (foot || wezterm || term.starts_with("mlterm")),
+            kitty_graphics: !multiplexed && (kitty || wezterm || ghostty),
+            iterm_images: !multiplexed && (iterm || wezterm),
+            cell_size: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RenderSettings;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Capabilities {
+        Capabilities::from_env(&|name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    /* a compiled entry whose names and booleans end on an odd offset,
+     * with max_colors as its last number */
+    fn terminfo(magic: i16, number_size: usize, colors: i32) -> Vec<u8> {
+        let names = b"test|\0";
+        let bools = [1, 0, 1];
+        let mut bytes = Vec::new();
+        for short in [magic, names.len() as i16, bools.len() as i16, 14, 0, 0] {
+            bytes.extend_from_slice(&short.to_le_bytes());
+        }
+        bytes.extend_from_slice(names);
+        bytes.extend_from_slice(&bools);
+        bytes.push(0);
+        for i in 0..14 {
+            let number = if i == TERMINFO_COLORS { colors } else { -1 };
+            bytes.extend_from_slice(&number.to_le_bytes()[..number_size]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn no_color_disables_colors() {
+        let capabilities = from_vars(&[("TERM", "xterm-256color"), ("NO_COLOR", "1")]);
+        assert!(capabilities.ansi);
+        assert_eq!(capabilities.color_mode, ColorMode::None);
+    }
+
+    #[test]
+    fn dumb_terminals_get_plain_output() {
+        let capabilities = from_vars(&[("TERM", "dumb"), ("COLORTERM", "truecolor")]);
+        assert!(!capabilities.ansi);
+        assert_eq!(capabilities.color_mode, ColorMode::None);
+        assert!(!capabilities.unicode);
+        assert!(RenderSettings::from_capabilities(&capabilities).plain);
+    }
+
+    #[test]
+    fn direct_and_colorterm_give_true_colors() {
+        for vars in [
+            &[("TERM", "xterm-direct")][..],
+            &[("TERM", "xterm"), ("COLORTERM", "truecolor")][..],
+            &[("TERM", "xterm"), ("COLORTERM", "24bit")][..],
+        ] {
+            assert_eq!(
+                from_vars(vars).color_mode,
+                ColorMode::TrueColor,
+                "{:?}",
+                vars
+            );
+        }
+    }
+
+    #[test]
+    fn multiplexers_disable_graphics() {
+        let kitty = [("TERM", "xterm-kitty"), ("KITTY_WINDOW_ID", "1")];
+        assert!(from_vars(&kitty).kitty_graphics);
+        let tmux = [
+            ("TERM", "tmux-256color"),
+            ("TMUX", "/tmp/tmux-1000/default,1,0"),
+            ("KITTY_WINDOW_ID", "1"),
+        ];
+        let capabilities = from_vars(&tmux);
+        assert!(!capabilities.kitty_graphics);
+        assert!(!capabilities.sixel);
+        assert!(!capabilities.iterm_images);
+        let foot = [("TERM", "foot"), ("TMUX", "/tmp/tmux-1000/default,1,0")];
+        assert!(!from_vars(&foot).sixel);
+    }
+
+    #[test]
+    fn cell_size_from_the_answer() {
+        let answer = "\x1b_Gi=31;OK\x1b\\\x1b[6;20;10t\x1b[?62;4;22c";
+        assert_eq!(cell_size(answer), Some((10, 20)));
+        assert_eq!(cell_size("\x1b[?62;4;22c"), None);
+        assert_eq!(cell_size("\x1b[6;0;0t\x1b[?62;4;22c"), None);
+    }
+
+    #[test]
+    fn terminfo_numbers_of_both_formats() {
+        assert_eq!(
+            terminfo_number(&terminfo(0o432, 2, 256), TERMINFO_COLORS),
+            Some(256)
+        );
+        assert_eq!(
+            terminfo_number(&terminfo(0o1036, 4, 1 << 24), TERMINFO_COLORS),
+            Some(1 << 24)
+        );
+        /* absent numbers are negative, and other files are not terminfo entries */
+        assert_eq!(terminfo_number(&terminfo(0o432, 2, 256), 0), None);
+        assert_eq!(
+            terminfo_number(&terminfo(0o433, 2, 256), TERMINFO_COLORS),
+            None
+        );
+        assert_eq!(
+            terminfo_number(&terminfo(0o432, 2, 256)[..20], TERMINFO_COLORS),
+            None
+        );
+    }
+
+    #[test]
+    fn terminfo_colors_are_read_from_terminfo() {
+        let directory =
+            std::env::temp_dir().join(format!("blockish-terminfo-{}", std::process::id()));
+        fs::create_dir_all(directory.join("b")).unwrap();
+        fs::write(
+            directory.join("b").join("blockish-test"),
+            terminfo(0o1036, 4, 1 << 24),
+        )
+        .unwrap();
+        let capabilities = from_vars(&[
+            ("TERM", "blockish-test"),
+            ("TERMINFO", directory.to_str().unwrap()),
+        ]);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(capabilities.color_mode, ColorMode::TrueColor);
+    }
+}